@@ -1,5 +1,4 @@
 use std::fs::File;
-use std::io::prelude::*;
 use std::path::PathBuf;
 
 pub fn main() {
@@ -4,9 +4,11 @@ use std::path::PathBuf;
 
 #[derive(Parser, Debug, ArgEnum, PartialEq, Eq, Clone)]
 pub enum ShellType{
-    // TODO add other shells
-    Zsh,
+    Sh,
     Bash,
+    Zsh,
+    Fish,
+    Nu,
 }
 
 #[derive(Parser, Debug)]
@@ -41,19 +43,108 @@ pub enum Commands {
     Import {
         #[clap(required = true, parse(from_os_str))]
         path: PathBuf,
+        /// Remove stored repos under PATH that no longer exist
+        #[clap(long)]
+        prune: bool,
     },
 
     /// Update the stored repos (status, remotes, branch, etc.)
-    #[clap(arg_required_else_help = true)]
     Update{
         #[clap(required = false, parse(from_os_str))]
-        path: Option<PathBuf>
+        path: Option<PathBuf>,
+        /// Number of repos to refresh at once
+        #[clap(short, long)]
+        jobs: Option<usize>,
+        /// Fetch all remotes first, so ahead/behind is compared against the latest remote state
+        #[clap(short, long)]
+        fetch: bool,
+    },
+
+    /// List repos, optionally only the ones matching a query (e.g. `tag:backend status:dirty`).
+    List {
+        query: Vec<String>,
+        /// Sort by these keys instead of the order chosen in the TUI (name, status, path,
+        /// recent, tag), e.g. `status,name`
+        #[clap(long)]
+        sort: Option<String>,
+        /// Reverse the sort order
+        #[clap(short, long)]
+        reverse: bool,
+        /// Only repos with this tag, can be given more than once
+        #[clap(short, long = "tag")]
+        tags: Vec<String>,
+        /// Print the repos as JSON
+        #[clap(long, conflicts_with = "paths")]
+        json: bool,
+        /// Print only the paths, one per line
+        #[clap(short, long)]
+        paths: bool,
+    },
+
+    /// Run a command in a set of repos, e.g. `rgm exec -t rust cargo update`.
+    ///
+    /// Everything from the command on is passed to it, put `--` in front of a command that starts
+    /// with a dash. A single argument is run through `sh -c`, so
+    /// `rgm exec -t rust 'make lint && make test'` works too.
+    #[clap(arg_required_else_help = true, trailing_var_arg = true)]
+    Exec {
+        /// Only repos with this tag, can be given more than once
+        #[clap(short, long = "tag")]
+        tags: Vec<String>,
+        /// Only repos under this directory
+        #[clap(short, long, parse(from_os_str))]
+        path: Option<PathBuf>,
+        /// Only repos matching this query, e.g. `status:dirty`
+        #[clap(short, long)]
+        query: Option<String>,
+        /// Run in every stored repo when nothing else selects them
+        #[clap(short, long)]
+        all: bool,
+        /// Number of repos to run in at once
+        #[clap(short, long)]
+        jobs: Option<usize>,
+        /// Show the output of each repo in one block once it finishes instead of prefixing
+        /// every line
+        #[clap(short, long)]
+        group: bool,
+        #[clap(required = true, multiple_values = true)]
+        command: Vec<String>,
+    },
+
+    /// Go to a repo by alias, name or query without the TUI, which opens filtered instead when
+    /// several repos match.
+    #[clap(visible_alias = "cd", arg_required_else_help = true)]
+    Go {
+        #[clap(required = true)]
+        query: Vec<String>,
+        /// Print the path instead of changing directory
+        #[clap(short, long)]
+        print: bool,
     },
 
     /// Initialize RGM
     #[clap(arg_required_else_help = true)]
     Init {
         #[clap(required = true, arg_enum)]
-        shell: ShellType
+        shell: ShellType,
+        /// Also bind Ctrl-G to open rgm
+        #[clap(short, long)]
+        bindings: bool,
+    },
+
+    /// Print tab completions for SHELL, e.g. `rgm-bin completions fish | source`. `rgm init`
+    /// already includes them
+    #[clap(arg_required_else_help = true)]
+    Completions {
+        #[clap(required = true, arg_enum)]
+        shell: ShellType,
+    },
+
+    /// Known repo names, aliases or tags to complete the words typed so far with, used by the
+    /// shell integration
+    #[clap(hide = true)]
+    Complete {
+        #[clap(last = true)]
+        words: Vec<String>,
     }
 }
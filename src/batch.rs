@@ -0,0 +1,141 @@
+// Git operations running in the background on a batch of repos, shown in a popup
+use crate::error::Result;
+use crate::gitops::GitOp;
+use crate::pool;
+use crate::screen::Draw;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Color, Style},
+    terminal::Frame,
+    text::{Span, Spans},
+    widgets::{Block, Borders, Clear, List, ListItem},
+};
+
+struct Entry {
+    // Index into the repo view's items
+    item: usize,
+    name: String,
+    result: Option<Result<String>>,
+}
+
+pub struct Batch {
+    op: GitOp,
+    entries: Vec<Entry>,
+    // Entry index and result of each finished repo
+    results: Receiver<(usize, Result<String>)>,
+}
+
+impl Batch {
+    /// Start running `op` on the `targets` (item index, name, path) on a background thread
+    pub fn start(op: GitOp, targets: Vec<(usize, String, PathBuf)>) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let work: Vec<(usize, PathBuf)> = targets
+            .iter()
+            .enumerate()
+            .map(|(entry, (_, _, path))| (entry, path.clone()))
+            .collect();
+        let job_op = op.clone();
+        thread::spawn(move || {
+            pool::run(
+                work,
+                pool::default_jobs(),
+                |(entry, path)| (entry, job_op.run(&path)),
+                |res| {
+                    // The TUI may have closed, nothing left to report to then
+                    let _ = tx.send(res);
+                },
+            )
+        });
+        let entries = targets
+            .into_iter()
+            .map(|(item, name, _)| Entry {
+                item,
+                name,
+                result: None,
+            })
+            .collect();
+        Self {
+            op,
+            entries,
+            results: rx,
+        }
+    }
+
+    /// Collect the results that came in since the last poll, returns the items they were for
+    pub fn poll(&mut self) -> Vec<usize> {
+        let mut finished = Vec::new();
+        while let Ok((entry, res)) = self.results.try_recv() {
+            let entry = &mut self.entries[entry];
+            entry.result = Some(res);
+            finished.push(entry.item);
+        }
+        finished
+    }
+
+    fn done(&self) -> usize {
+        self.entries.iter().filter(|e| e.result.is_some()).count()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done() == self.entries.len()
+    }
+
+    pub fn summary(&self) -> String {
+        let failed = self
+            .entries
+            .iter()
+            .filter(|e| matches!(e.result, Some(Err(_))))
+            .count();
+        format!(
+            "{}: {} done, {} failed",
+            self.op,
+            self.done() - failed,
+            failed
+        )
+    }
+}
+
+impl Draw for Batch {
+    fn draw<B: Backend>(&mut self, frame: &mut Frame<B>, area: Rect) {
+        // Centered, leaving a margin of the list visible around it
+        let width = area.width.saturating_sub(8).max(20).min(area.width);
+        let height = (self.entries.len() as u16 + 2)
+            .min(area.height.saturating_sub(4))
+            .max(3.min(area.height));
+        let popup = Rect::new(
+            area.x + (area.width - width) / 2,
+            area.y + (area.height - height) / 2,
+            width,
+            height,
+        );
+        let name_width = self.entries.iter().map(|e| e.name.len()).max().unwrap_or(0);
+        let items: Vec<ListItem> = self
+            .entries
+            .iter()
+            .map(|e| {
+                let (mark, msg, color) = match &e.result {
+                    None => ("…", String::new(), Color::Gray),
+                    Some(Ok(msg)) => ("✓", msg.clone(), Color::Green),
+                    Some(Err(e)) => ("✗", e.to_string(), Color::Red),
+                };
+                ListItem::new(Spans::from(vec![
+                    Span::styled(format!("{} ", mark), Style::default().fg(color)),
+                    Span::raw(format!("{:width$}  ", e.name, width = name_width)),
+                    Span::styled(msg, Style::default().fg(color)),
+                ]))
+            })
+            .collect();
+        let title = if self.is_done() {
+            format!("{}, Esc to close", self.summary())
+        } else {
+            format!("{} ({}/{})", self.op, self.done(), self.entries.len())
+        };
+        let list = List::new(items).block(Block::default().borders(Borders::ALL).title(title));
+        frame.render_widget(Clear, popup);
+        frame.render_widget(list, popup);
+    }
+}
@@ -0,0 +1,212 @@
+// Tab completion: static clap completions, plus candidates from the store for the
+// arguments that take repo names, aliases or tags, `rgm-bin complete -- <words>`
+use clap::CommandFactory;
+use clap_complete::{generate, Shell};
+
+use crate::args::{Cli, ShellType};
+use crate::repo::{Repo, Repos};
+
+/// Subcommands that take a repo alias or name
+const REPO_COMMANDS: [&str; 2] = ["go", "cd"];
+/// Options that take a tag
+const TAG_OPTIONS: [&str; 2] = ["-t", "--tag"];
+
+/// Visible subcommands and their aliases
+fn subcommands() -> Vec<String> {
+    Cli::command()
+        .get_subcommands()
+        .filter(|c| !c.is_hide_set())
+        .flat_map(|c| {
+            let mut names = vec![c.get_name().to_string()];
+            names.extend(c.get_visible_aliases().map(String::from));
+            names
+        })
+        .collect()
+}
+
+/// Aliases first, then names, without duplicates
+fn repo_names(repos: &[Repo]) -> Vec<String> {
+    let mut names: Vec<String> = repos.iter().filter_map(|r| r.alias.clone()).collect();
+    for repo in repos {
+        if !names.contains(&repo.name) {
+            names.push(repo.name.clone());
+        }
+    }
+    names
+}
+
+/// Every tag in use, without duplicates
+fn tags(repos: &[Repo]) -> Vec<String> {
+    let mut tags: Vec<String> = repos.iter().flat_map(|r| r.tags.iter().cloned()).collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// What the word after `words` is, judging by the command line so far
+#[derive(Debug, PartialEq, Eq)]
+enum Expected {
+    Subcommand,
+    RepoName,
+    Tag,
+    // Left to the static completions
+    Other,
+}
+
+fn expected(words: &[String]) -> Expected {
+    let args: Vec<&str> = words
+        .iter()
+        .skip(1)
+        .map(String::as_str)
+        .filter(|w| !w.starts_with('-'))
+        .collect();
+    let prev = words.last().map(String::as_str).unwrap_or_default();
+    match args.first() {
+        None => Expected::Subcommand,
+        Some(_) if TAG_OPTIONS.contains(&prev) => Expected::Tag,
+        Some(cmd) if REPO_COMMANDS.contains(cmd) => Expected::RepoName,
+        // `rgm tag <path> <tags>...`
+        Some(&"tag") if args.len() >= 2 => Expected::Tag,
+        Some(_) => Expected::Other,
+    }
+}
+
+/// Candidates for the word after `words`, which start with the command itself. The shell
+/// narrows them down to the ones matching what was typed of the word, and falls back to the
+/// static completions when there are none.
+pub fn candidates(words: &[String]) -> Vec<String> {
+    let from_store = |f: fn(&[Repo]) -> Vec<String>| match Repos::read_only() {
+        Ok(repos) => f(&repos.repos),
+        Err(_) => vec![],
+    };
+    match expected(words) {
+        Expected::Subcommand => subcommands(),
+        Expected::RepoName => from_store(repo_names),
+        Expected::Tag => from_store(tags),
+        Expected::Other => vec![],
+    }
+}
+
+// Candidates are filtered here rather than with compgen -W, which would expand quotes and
+// variables in repo names. The first word is left to the static `_rgm`, it knows the options
+const BASH: &str = r#"
+_rgm_complete() {
+    local candidates
+    if [[ ${COMP_CWORD} -gt 1 ]]; then
+        candidates=$(rgm-bin complete -- "${COMP_WORDS[@]:0:COMP_CWORD}" 2>/dev/null)
+    fi
+    if [[ -n "${candidates}" ]]; then
+        local cur="${COMP_WORDS[COMP_CWORD]}" candidate
+        COMPREPLY=()
+        while IFS= read -r candidate; do
+            [[ "${candidate}" == "${cur}"* ]] && COMPREPLY+=("${candidate}")
+        done <<< "${candidates}"
+    else
+        _rgm "$@"
+    fi
+}
+complete -F _rgm_complete -o bashdefault -o default rgm
+"#;
+
+const ZSH: &str = r#"
+_rgm_complete() {
+    local -a candidates
+    if (( CURRENT > 2 )); then
+        candidates=(${(f)"$(rgm-bin complete -- ${words[1,CURRENT-1]} 2>/dev/null)"})
+    fi
+    if (( ${#candidates} )); then
+        compadd -a candidates
+    else
+        _rgm "$@"
+    fi
+}
+
+# Autoloaded from $fpath, or sourced
+if [ "$funcstack[1]" = "_rgm" ]; then
+    _rgm_complete "$@"
+elif (( $+functions[compdef] )); then
+    compdef _rgm_complete rgm
+fi
+"#;
+
+// Only the repo commands skip file completion, `rgm tag` takes a path first
+const FISH: &str = r#"
+complete -c rgm -n "__fish_seen_subcommand_from go cd" -f -a '(rgm-bin complete -- (commandline -opc) 2>/dev/null)'
+complete -c rgm -n "__fish_seen_subcommand_from tag list exec" -a '(rgm-bin complete -- (commandline -opc) 2>/dev/null)'
+"#;
+
+const NU: &str = r#"def "nu-complete rgm" [context: string] {
+    ^rgm-bin complete -- ...($context | split row " " | drop 1) | lines
+}
+"#;
+
+/// Static completions of every subcommand and option, completing the `rgm` wrapper function
+fn static_completions(shell: Shell) -> String {
+    let mut buf = Vec::new();
+    generate(shell, &mut Cli::command(), "rgm", &mut buf);
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+/// Tab completion for the `rgm` wrapper function in `shell`: the static completions, plus a
+/// hook asking `rgm-bin complete` for repo names, aliases and tags from the store
+pub fn completions(shell: &ShellType) -> String {
+    match shell {
+        ShellType::Sh => String::from("# POSIX sh has no tab completion\n"),
+        ShellType::Bash => format!("{}{}", static_completions(Shell::Bash), BASH),
+        // The static script ends by running `_rgm`, which only works when autoloaded
+        ShellType::Zsh => {
+            let script = static_completions(Shell::Zsh);
+            format!("{}{}", script.trim_end().trim_end_matches(r#"_rgm "$@""#), ZSH)
+        }
+        ShellType::Fish => format!("{}{}", static_completions(Shell::Fish), FISH),
+        // Nushell has no generator, its completer covers the subcommands too
+        ShellType::Nu => String::from(NU),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::repo;
+
+    #[test]
+    fn test_candidates() {
+        let commands = candidates(&[String::from("rgm")]);
+        assert!(commands.contains(&String::from("go")) && commands.contains(&String::from("cd")));
+        assert!(!commands.contains(&String::from("complete")));
+        let words = ["rgm", "list", "--json"].map(String::from);
+        assert!(candidates(&words).is_empty());
+    }
+
+    #[test]
+    fn test_expected() {
+        let expected = |words: &[&str]| expected(&words.iter().map(|w| String::from(*w)).collect::<Vec<_>>());
+        assert_eq!(expected(&["rgm"]), Expected::Subcommand);
+        assert_eq!(expected(&["rgm", "cd"]), Expected::RepoName);
+        assert_eq!(expected(&["rgm", "go", "-p"]), Expected::RepoName);
+        assert_eq!(expected(&["rgm", "tag"]), Expected::Other);
+        assert_eq!(expected(&["rgm", "tag", "src/", "rust"]), Expected::Tag);
+        assert_eq!(expected(&["rgm", "list", "--tag"]), Expected::Tag);
+        assert_eq!(expected(&["rgm", "exec", "-t"]), Expected::Tag);
+        assert_eq!(expected(&["rgm", "list", "-t", "rust"]), Expected::Other);
+    }
+
+    #[test]
+    fn test_completions() {
+        let zsh = completions(&ShellType::Zsh);
+        assert!(zsh.starts_with("#compdef rgm") && zsh.contains("compdef _rgm_complete rgm"));
+        assert!(!zsh.contains("\n_rgm \"$@\"\n"));
+        assert!(completions(&ShellType::Bash).contains("complete -F _rgm_complete"));
+        assert!(completions(&ShellType::Fish).contains("__fish_seen_subcommand_from go cd"));
+    }
+
+    #[test]
+    fn test_repo_names() {
+        let aliased = |name: &str, alias: &str| Repo {
+            alias: Some(String::from(alias)),
+            ..repo(name, format!("/src/{}", name), &[])
+        };
+        let repos = vec![repo("api", "/src/api", &[]), aliased("web", "api"), aliased("web", "w")];
+        assert_eq!(repo_names(&repos), vec!["api", "w", "web"]);
+    }
+}
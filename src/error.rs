@@ -11,3 +11,27 @@ impl fmt::Display for RgmError {
         write!(f, "{}", self.message)
     }
 }
+
+impl From<std::io::Error> for RgmError {
+    fn from(err: std::io::Error) -> Self {
+        RgmError {
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<serde_json::Error> for RgmError {
+    fn from(err: serde_json::Error) -> Self {
+        RgmError {
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<git2::Error> for RgmError {
+    fn from(err: git2::Error) -> Self {
+        RgmError {
+            message: err.message().to_string(),
+        }
+    }
+}
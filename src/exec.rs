@@ -0,0 +1,244 @@
+// Running a shell command in many repos at once, `rgm exec`
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+
+use crate::error::{Result, RgmError};
+use crate::pool;
+use crate::query::Query;
+use crate::repo::Repo;
+
+/// Which repos a command runs in, all given parts have to match
+#[derive(Debug, Default)]
+pub struct Selection {
+    pub tags: Vec<String>,
+    // Canonical path the repos have to be under
+    pub path: Option<PathBuf>,
+    pub query: Option<Query>,
+}
+
+impl Selection {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.path.is_none() && self.query.is_none()
+    }
+
+    pub fn matches(&self, repo: &Repo) -> bool {
+        self.tags.iter().all(|t| repo.tags.contains(t))
+            && self.path.as_ref().map(|p| repo.path.starts_with(p)).unwrap_or(true)
+            && self.query.as_ref().map(|q| q.matches(repo)).unwrap_or(true)
+    }
+}
+
+/// How the output of the repos is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    // Every line as it comes in, prefixed with the repo name
+    Prefix,
+    // All output of a repo at once when it finishes, under a header
+    Group,
+}
+
+/// How a command ended in one repo
+#[derive(Debug)]
+pub enum Outcome {
+    Success,
+    Failed(ExitStatus),
+    // The command could not be started at all
+    Error(RgmError),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Success => write!(f, "ok"),
+            Outcome::Failed(status) => match status.code() {
+                Some(code) => write!(f, "exit code {}", code),
+                None => write!(f, "killed by a signal"),
+            },
+            Outcome::Error(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Outcome of the command in every repo, in completion order
+#[derive(Debug, Default)]
+pub struct ExecSummary {
+    pub results: Vec<(String, Outcome)>,
+}
+
+impl ExecSummary {
+    pub fn failed(&self) -> Vec<&(String, Outcome)> {
+        self.results
+            .iter()
+            .filter(|(_, o)| !matches!(o, Outcome::Success))
+            .collect()
+    }
+}
+
+impl fmt::Display for ExecSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let failed = self.failed();
+        write!(
+            f,
+            "Ran in {} repos, {} succeeded, {} failed",
+            self.results.len(),
+            self.results.len() - failed.len(),
+            failed.len()
+        )?;
+        for (name, outcome) in failed {
+            write!(f, "\n  {}: {}", name, outcome)?;
+        }
+        Ok(())
+    }
+}
+
+/// A single argument is run by `sh -c` so pipes and `&&` work, several are run as is
+fn command(cmd: &[String], dir: &Path) -> Command {
+    let mut command = if cmd.len() == 1 {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(&cmd[0]);
+        c
+    } else {
+        let mut c = Command::new(&cmd[0]);
+        c.args(&cmd[1..]);
+        c
+    };
+    command.current_dir(dir).stdin(Stdio::null());
+    command
+}
+
+/// Print every line of `from` prefixed with `name`, stdout and stderr lines are written whole
+/// so they don't interleave mid line between repos
+fn forward_lines<R: Read>(from: R, name: &str, width: usize, stderr: bool) {
+    for line in BufReader::new(from).lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => break,
+        };
+        let line = format!("{:width$} | {}\n", name, line, width = width);
+        let _ = if stderr {
+            io::stderr().lock().write_all(line.as_bytes())
+        } else {
+            io::stdout().lock().write_all(line.as_bytes())
+        };
+    }
+}
+
+fn run_prefixed(cmd: &[String], name: &str, dir: &Path, width: usize) -> Result<ExitStatus> {
+    let mut child = command(cmd, dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    thread::scope(|scope| {
+        if let Some(err) = stderr {
+            scope.spawn(move || forward_lines(err, name, width, true));
+        }
+        if let Some(out) = stdout {
+            forward_lines(out, name, width, false);
+        }
+    });
+    Ok(child.wait()?)
+}
+
+fn print_group(name: &str, outcome: &Outcome, stdout: &[u8], stderr: &[u8]) {
+    let out = io::stdout();
+    let mut out = out.lock();
+    let _ = writeln!(out, "==> {} ({})", name, outcome);
+    let _ = out.write_all(stdout);
+    let _ = out.write_all(stderr);
+    if !stdout.ends_with(b"\n") || !stderr.is_empty() && !stderr.ends_with(b"\n") {
+        let _ = writeln!(out);
+    }
+}
+
+/// Run `cmd` in every repo of `repos` with at most `jobs` at once
+pub fn run(repos: &[&Repo], cmd: &[String], jobs: usize, output: Output) -> ExecSummary {
+    let mut summary = ExecSummary::default();
+    if cmd.is_empty() {
+        return summary;
+    }
+    let width = repos.iter().map(|r| r.name.len()).max().unwrap_or(0);
+    let work: Vec<(String, PathBuf)> = repos
+        .iter()
+        .map(|r| (r.name.clone(), r.path.clone()))
+        .collect();
+    pool::run(
+        work,
+        jobs,
+        |(name, path)| {
+            let outcome = match output {
+                Output::Prefix => run_prefixed(cmd, &name, &path, width),
+                Output::Group => command(cmd, &path)
+                    .output()
+                    .map_err(RgmError::from)
+                    .map(|out| {
+                        let status = out.status;
+                        let outcome = outcome(Ok(status));
+                        print_group(&name, &outcome, &out.stdout, &out.stderr);
+                        status
+                    }),
+            };
+            (name, outcome)
+        },
+        |(name, res)| summary.results.push((name, outcome(res))),
+    );
+    summary
+}
+
+fn outcome(res: Result<ExitStatus>) -> Outcome {
+    match res {
+        Ok(status) if status.success() => Outcome::Success,
+        Ok(status) => Outcome::Failed(status),
+        Err(e) => Outcome::Error(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::{repo, test_dir};
+    use std::fs;
+
+    #[test]
+    fn test_selection() {
+        let api = repo("api", Path::new("/src/work/api"), &["backend", "rust"]);
+        let web = repo("web", Path::new("/src/home/web"), &["frontend"]);
+        let mut sel = Selection::default();
+        assert!(sel.is_empty() && sel.matches(&api) && sel.matches(&web));
+        sel.tags = vec![String::from("backend"), String::from("rust")];
+        assert!(sel.matches(&api) && !sel.matches(&web));
+        sel.tags.clear();
+        sel.path = Some(PathBuf::from("/src/home"));
+        assert!(!sel.matches(&api) && sel.matches(&web));
+        sel.query = Some(Query::parse("api").unwrap());
+        assert!(!sel.matches(&web));
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = test_dir("exec");
+        fs::create_dir_all(dir.join("one")).unwrap();
+        fs::create_dir_all(dir.join("two")).unwrap();
+        fs::write(dir.join("one/marker"), "").unwrap();
+        let one = repo("one", dir.join("one"), &[]);
+        let two = repo("two", dir.join("two"), &[]);
+        let gone = repo("gone", dir.join("gone"), &[]);
+
+        let summary = run(&[&one, &two], &[String::from("test -f marker")], 2, Output::Group);
+        assert_eq!(summary.results.len(), 2);
+        let failed: Vec<&str> = summary.failed().iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(failed, vec!["two"]);
+
+        let cmd = vec![String::from("ls"), String::from("marker")];
+        let summary = run(&[&one, &gone], &cmd, 1, Output::Prefix);
+        let failed = summary.failed();
+        assert_eq!(failed.len(), 1);
+        assert!(matches!(failed[0], (n, Outcome::Error(_)) if n == "gone"));
+        assert!(summary.to_string().starts_with("Ran in 2 repos, 1 succeeded, 1 failed\n  gone: "));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
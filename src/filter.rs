@@ -0,0 +1,104 @@
+// Filters applied to the repo list in the TUI
+use crate::query::Query;
+use crate::repo::Repo;
+
+/// Stack of applied filters, each one narrowing down the ones before it, plus the filter
+/// currently being typed.
+#[derive(Debug, Default)]
+pub struct Filters {
+    // Applied filters with the text they were typed as
+    stack: Vec<(String, Query)>,
+    // Previewed on top of the stack until it is applied or cancelled
+    preview: Option<Query>,
+}
+
+impl Filters {
+    pub fn push(&mut self, text: String, query: Query) {
+        self.preview = None;
+        self.stack.push((text, query));
+    }
+
+    /// Remove the most recent filter, returns false if there was none
+    pub fn pop(&mut self) -> bool {
+        self.stack.pop().is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.stack.clear();
+        self.preview = None;
+    }
+
+    pub fn preview(&mut self, query: Query) {
+        self.preview = Some(query);
+    }
+
+    /// Drop the previewed filter, returns false if there was none
+    pub fn cancel_preview(&mut self) -> bool {
+        self.preview.take().is_some()
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.stack.is_empty() || self.preview.is_some()
+    }
+
+    fn queries(&self) -> impl Iterator<Item = &Query> {
+        self.stack.iter().map(|(_, q)| q).chain(self.preview.iter())
+    }
+
+    /// Filters are typed to narrow down the list quickly, so bare words match fuzzily
+    pub fn matches(&self, repo: &Repo) -> bool {
+        self.queries().all(|q| q.matches_fuzzy(repo))
+    }
+
+    /// Fuzzy terms of all filters, used for ranking and highlighting
+    pub fn fuzzy_terms(&self) -> Vec<&str> {
+        self.queries().flat_map(|q| q.fuzzy_terms()).collect()
+    }
+
+    /// Short description of the applied filters, e.g. `api > tag:backend`
+    pub fn describe(&self) -> String {
+        self.stack
+            .iter()
+            .map(|(text, _)| text.as_str())
+            .collect::<Vec<&str>>()
+            .join(" > ")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::repo;
+
+    fn push(filters: &mut Filters, text: &str) {
+        filters.push(String::from(text), Query::parse(text).unwrap());
+    }
+
+    #[test]
+    fn test_filter_stack() {
+        let api = repo("api", "/tmp/api", &["backend"]);
+        let web = repo("web-api", "/tmp/web-api", &["frontend"]);
+        let mut filters = Filters::default();
+        assert!(!filters.is_active());
+        push(&mut filters, "api");
+        assert!(filters.matches(&api) && filters.matches(&web));
+        push(&mut filters, "tag:backend");
+        assert!(filters.matches(&api) && !filters.matches(&web));
+        assert_eq!(filters.describe(), "api > tag:backend");
+        assert_eq!(filters.fuzzy_terms(), vec!["api"]);
+
+        filters.preview(Query::parse("web").unwrap());
+        assert!(!filters.matches(&api));
+        assert!(filters.cancel_preview());
+        assert!(filters.matches(&api));
+
+        assert!(filters.pop());
+        assert!(filters.matches(&web));
+        filters.clear();
+        assert!(!filters.is_active());
+        assert!(!filters.pop());
+
+        push(&mut filters, "wapi");
+        assert!(!filters.matches(&api) && filters.matches(&web));
+    }
+}
@@ -0,0 +1,122 @@
+// fzf style fuzzy matching, used to filter and rank repos by name and alias
+
+// Score for each matched character
+const SCORE_MATCH: i64 = 16;
+// Bonus for matching the first character or right after a separator/case change
+const BONUS_BOUNDARY: i64 = 8;
+// Bonus for every character that directly follows the previous match
+const BONUS_CONSECUTIVE: i64 = 4;
+// Penalties for skipping characters in between matches
+const PENALTY_GAP_START: i64 = 3;
+const PENALTY_GAP_EXTENSION: i64 = 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Match {
+    pub score: i64,
+    // Char indices into the matched text
+    pub positions: Vec<usize>,
+}
+
+fn is_boundary(prev: Option<char>, curr: char) -> bool {
+    match prev {
+        None => true,
+        Some(p) => {
+            !p.is_alphanumeric() || (p.is_lowercase() && curr.is_uppercase())
+                || (!p.is_numeric() && curr.is_numeric())
+        }
+    }
+}
+
+/// Match all chars of `pattern` in order in `text`, case insensitive.
+///
+/// Like fzf's v1 algorithm: find the first occurrence going forward, then walk back from its end
+/// to find the shortest window containing the pattern, and score the match in that window.
+pub fn fuzzy_match(pattern: &str, text: &str) -> Option<Match> {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let chars: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+    if pattern.is_empty() {
+        return Some(Match {
+            score: 0,
+            positions: vec![],
+        });
+    }
+
+    // Forward, find the end of the first full match
+    let mut p = 0;
+    let mut end = None;
+    for (i, c) in lower.iter().enumerate() {
+        if *c == pattern[p] {
+            p += 1;
+            if p == pattern.len() {
+                end = Some(i);
+                break;
+            }
+        }
+    }
+    let end = end?;
+
+    // Backward, shrink the window from the end
+    let mut positions = Vec::with_capacity(pattern.len());
+    let mut p = pattern.len();
+    for i in (0..=end).rev() {
+        if lower[i] == pattern[p - 1] {
+            positions.push(i);
+            p -= 1;
+            if p == 0 {
+                break;
+            }
+        }
+    }
+    positions.reverse();
+
+    let mut score = 0;
+    let mut prev: Option<usize> = None;
+    for &pos in positions.iter() {
+        score += SCORE_MATCH;
+        if is_boundary(pos.checked_sub(1).map(|i| chars[i]), chars[pos]) {
+            score += BONUS_BOUNDARY;
+        }
+        if let Some(prev) = prev {
+            let gap = pos - prev - 1;
+            if gap == 0 {
+                score += BONUS_CONSECUTIVE;
+            } else {
+                score -= PENALTY_GAP_START + PENALTY_GAP_EXTENSION * (gap as i64 - 1);
+            }
+        }
+        prev = Some(pos);
+    }
+    Some(Match { score, positions })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert!(fuzzy_match("xyz", "api-server").is_none());
+        assert_eq!(fuzzy_match("asv", "api-server").unwrap().positions, vec![0, 4, 7]);
+        assert_eq!(fuzzy_match("API", "web-api").unwrap().positions, vec![4, 5, 6]);
+        assert_eq!(fuzzy_match("", "anything").unwrap().score, 0);
+    }
+
+    #[test]
+    fn test_fuzzy_ranking() {
+        let score = |p, t| fuzzy_match(p, t).unwrap().score;
+        // Consecutive beats scattered
+        assert!(score("api", "api-gateway") > score("api", "a-pretty-icon"));
+        // Word boundaries beat the middle of words
+        assert!(score("g", "api-gateway") > score("g", "angle"));
+        assert!(score("ss", "SomeService") > score("ss", "class"));
+        // Shortest window is used
+        assert_eq!(
+            fuzzy_match("ab", "a-xxxxx-ab").unwrap().positions,
+            vec![8, 9]
+        );
+    }
+}
@@ -0,0 +1,459 @@
+// Git operations that can be run on many repos at once
+use git2::{
+    build::CheckoutBuilder, AutotagOption, BranchType, Config, Cred, CredentialType, FetchOptions,
+    PushOptions, Remote, RemoteCallbacks, Repository,
+};
+use std::cell::RefCell;
+use std::fmt;
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{Result, RgmError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GitOp {
+    // Fetch the remote of the current branch's upstream (or origin)
+    Fetch,
+    // Fetch every remote
+    FetchAll,
+    // Fetch, then fast-forward the current branch to its upstream
+    Pull,
+    // Push the current branch to its upstream
+    Push,
+    // Switch to a local branch, or create one tracking a remote branch of that name
+    Checkout(String),
+}
+
+impl GitOp {
+    /// Parse a TUI command like `pull --ff-only` or `checkout main`
+    pub fn parse(cmd: &str, args: &[&str]) -> Result<Self> {
+        let args: Vec<&str> = args.iter().copied().filter(|a| !a.is_empty()).collect();
+        let op = match (cmd, &args[..]) {
+            ("fetch", []) => GitOp::Fetch,
+            ("fetch", ["--all"]) => GitOp::FetchAll,
+            // Only fast-forwards are done, merges and rebases are left to git
+            ("pull", []) | ("pull", ["--ff-only"]) => GitOp::Pull,
+            ("push", []) => GitOp::Push,
+            ("checkout", [branch]) | ("co", [branch]) => GitOp::Checkout(String::from(*branch)),
+            ("checkout", _) | ("co", _) => {
+                return Err(RgmError {
+                    message: String::from("Usage: :checkout <branch>"),
+                })
+            }
+            _ => {
+                return Err(RgmError {
+                    message: format!("Unsupported arguments for {}: {}", cmd, args.join(" ")),
+                })
+            }
+        };
+        Ok(op)
+    }
+
+    /// Run the operation on the repo at `path`, returns a short description of what happened
+    pub fn run(&self, path: &Path) -> Result<String> {
+        let raw = Repository::open(path)?;
+        match self {
+            GitOp::Fetch => fetch(&raw),
+            GitOp::FetchAll => fetch_all(&raw),
+            GitOp::Pull => pull(&raw),
+            GitOp::Push => push(&raw),
+            GitOp::Checkout(branch) => checkout(&raw, branch),
+        }
+    }
+}
+
+impl fmt::Display for GitOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GitOp::Fetch => write!(f, "fetch"),
+            GitOp::FetchAll => write!(f, "fetch --all"),
+            GitOp::Pull => write!(f, "pull --ff-only"),
+            GitOp::Push => write!(f, "push"),
+            GitOp::Checkout(branch) => write!(f, "checkout {}", branch),
+        }
+    }
+}
+
+/// Callbacks answering credential requests without prompting, the TUI owns the terminal.
+///
+/// Each kind of credential is only tried once, libgit2 keeps asking as long as we give it one.
+pub fn callbacks<'a>(config: Config) -> RemoteCallbacks<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    let mut tried = CredentialType::empty();
+    callbacks.credentials(move |url, username, allowed| {
+        let username = username.unwrap_or("git");
+        if allowed.contains(CredentialType::SSH_KEY) && !tried.contains(CredentialType::SSH_KEY) {
+            tried |= CredentialType::SSH_KEY;
+            return Cred::ssh_key_from_agent(username);
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT)
+            && !tried.contains(CredentialType::USER_PASS_PLAINTEXT)
+        {
+            tried |= CredentialType::USER_PASS_PLAINTEXT;
+            return Cred::credential_helper(&config, url, Some(username));
+        }
+        if allowed.contains(CredentialType::DEFAULT) && !tried.contains(CredentialType::DEFAULT) {
+            tried |= CredentialType::DEFAULT;
+            return Cred::default();
+        }
+        Err(git2::Error::from_str(
+            "No usable credentials (tried the ssh agent and git credential helpers)",
+        ))
+    });
+    callbacks
+}
+
+/// Longest a fetch of a single remote may take
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Fetch `name` with the refspecs configured for it, giving up once `FETCH_TIMEOUT` passed.
+fn fetch_remote(raw: &Repository, name: &str) -> Result<usize> {
+    fetch_remote_within(raw, name, FETCH_TIMEOUT)
+}
+
+/// libgit2 has no connect timeout, so the fetch runs on a thread of its own that is left behind
+/// when it doesn't finish in time, e.g. on a server that never answers. Once left behind it
+/// stops at the next progress callback, and it never writes refs or `FETCH_HEAD` after the
+/// timeout, so a timed out fetch leaves the repo as it was.
+fn fetch_remote_within(raw: &Repository, name: &str, timeout: Duration) -> Result<usize> {
+    let deadline = Instant::now() + timeout;
+    let path = raw.path().to_path_buf();
+    let remote = name.to_string();
+    let abandoned = Arc::new(Mutex::new(false));
+    let (tx, rx) = mpsc::channel();
+    {
+        let abandoned = Arc::clone(&abandoned);
+        thread::spawn(move || fetch_until(&path, &remote, deadline, &abandoned, tx));
+    }
+    let timed_out = || RgmError {
+        message: format!("Fetching {} timed out after {}s", name, timeout.as_secs()),
+    };
+    match rx.recv_timeout(timeout) {
+        Ok(Err(_)) if Instant::now() >= deadline => Err(timed_out()),
+        Ok(res) => res,
+        Err(_) => {
+            // Waits for the thread if it is writing the fetched refs already
+            *abandoned.lock().unwrap() = true;
+            rx.try_recv().unwrap_or_else(|_| Err(timed_out()))
+        }
+    }
+}
+
+/// Download, then write the refs unless the fetch was `abandoned` meanwhile. The result is
+/// sent while holding `abandoned`, so it either arrives or nothing was written.
+fn fetch_until(
+    path: &Path,
+    name: &str,
+    deadline: Instant,
+    abandoned: &Mutex<bool>,
+    tx: mpsc::Sender<Result<usize>>,
+) {
+    let raw = match Repository::open(path) {
+        Ok(raw) => raw,
+        Err(e) => {
+            let _ = tx.send(Err(e.into()));
+            return;
+        }
+    };
+    let downloaded = download(&raw, name, deadline);
+    let abandoned = abandoned.lock().unwrap();
+    if *abandoned {
+        return;
+    }
+    let res = downloaded.and_then(|(mut remote, received)| {
+        remote.update_tips(None, true, AutotagOption::Unspecified, None)?;
+        if prune_configured(&raw, name) {
+            remote.prune(None)?;
+        }
+        Ok(received)
+    });
+    let _ = tx.send(res);
+}
+
+/// Download what `name` has that we don't, returns the remote and how many objects came in
+fn download<'a>(
+    raw: &'a Repository,
+    name: &str,
+    deadline: Instant,
+) -> Result<(Remote<'a>, usize)> {
+    let mut remote = raw.find_remote(name)?;
+    let mut callbacks = callbacks(raw.config()?);
+    callbacks.transfer_progress(|_| Instant::now() < deadline);
+    callbacks.sideband_progress(|_| Instant::now() < deadline);
+    let mut opts = FetchOptions::new();
+    opts.remote_callbacks(callbacks);
+    remote.download(&[] as &[&str], Some(&mut opts))?;
+    let received = remote.stats().received_objects();
+    remote.disconnect()?;
+    Ok((remote, received))
+}
+
+/// Whether `remote.<name>.prune` or `fetch.prune` asks to prune on fetch, like `git fetch`
+fn prune_configured(raw: &Repository, name: &str) -> bool {
+    let config = match raw.config() {
+        Ok(config) => config,
+        Err(_) => return false,
+    };
+    config
+        .get_bool(&format!("remote.{}.prune", name))
+        .or_else(|_| config.get_bool("fetch.prune"))
+        .unwrap_or(false)
+}
+
+/// Full name of the checked out branch, e.g. `refs/heads/main`
+fn current_branch(raw: &Repository) -> Result<String> {
+    let head = raw.head()?;
+    if !head.is_branch() {
+        return Err(RgmError {
+            message: String::from("Not on a branch"),
+        });
+    }
+    Ok(head.name().unwrap_or_default().to_string())
+}
+
+/// Remote of the current branch's upstream, or origin
+fn upstream_remote(raw: &Repository) -> Result<String> {
+    let upstream = current_branch(raw)
+        .ok()
+        .and_then(|branch| raw.branch_upstream_remote(&branch).ok())
+        .and_then(|name| name.as_str().map(String::from));
+    match upstream {
+        Some(name) => Ok(name),
+        None if raw.find_remote("origin").is_ok() => Ok(String::from("origin")),
+        None => Err(RgmError {
+            message: String::from("No upstream or origin remote"),
+        }),
+    }
+}
+
+fn fetch(raw: &Repository) -> Result<String> {
+    let name = upstream_remote(raw)?;
+    let received = fetch_remote(raw, &name)?;
+    Ok(format!("Fetched {} ({} objects)", name, received))
+}
+
+/// Fetch every remote, trying all of them even if one fails
+fn fetch_all(raw: &Repository) -> Result<String> {
+    let remotes = raw.remotes()?;
+    let names: Vec<&str> = remotes.iter().flatten().collect();
+    let mut received = 0;
+    let mut errors = Vec::new();
+    for name in names.iter() {
+        match fetch_remote(raw, name) {
+            Ok(n) => received += n,
+            Err(e) => errors.push(format!("{}: {}", name, e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(RgmError {
+            message: errors.join(", "),
+        });
+    }
+    Ok(format!("Fetched {} remotes ({} objects)", names.len(), received))
+}
+
+fn pull(raw: &Repository) -> Result<String> {
+    let branch = current_branch(raw)?;
+    let upstream = raw.branch_upstream_name(&branch).map_err(|_| RgmError {
+        message: String::from("No upstream to pull from"),
+    })?;
+    fetch(raw)?;
+    let upstream = raw.find_reference(upstream.as_str().unwrap_or_default())?;
+    let theirs = raw.reference_to_annotated_commit(&upstream)?;
+    let (analysis, _) = raw.merge_analysis(&[&theirs])?;
+    if analysis.is_up_to_date() {
+        return Ok(String::from("Already up to date"));
+    }
+    if !analysis.is_fast_forward() {
+        return Err(RgmError {
+            message: String::from("Can't fast-forward, merge or rebase by hand"),
+        });
+    }
+    let ours = raw.head()?.peel_to_commit()?.id();
+    let (commits, _) = raw.graph_ahead_behind(theirs.id(), ours)?;
+    // Safe checkout refuses to overwrite local changes
+    let target = raw.find_object(theirs.id(), None)?;
+    raw.checkout_tree(&target, Some(CheckoutBuilder::new().safe()))?;
+    raw.find_reference(&branch)?
+        .set_target(theirs.id(), "rgm: pull --ff-only")?;
+    Ok(format!("Fast-forwarded {} commits", commits))
+}
+
+fn push(raw: &Repository) -> Result<String> {
+    let branch = current_branch(raw)?;
+    let short = branch.trim_start_matches("refs/heads/");
+    let no_upstream = || RgmError {
+        message: format!("{} has no upstream, push it once with `git push -u`", short),
+    };
+    let remote_name = raw.branch_upstream_remote(&branch).map_err(|_| no_upstream())?;
+    let remote_name = remote_name.as_str().unwrap_or_default().to_string();
+    let merge = raw
+        .config()?
+        .get_string(&format!("branch.{}.merge", short))
+        .map_err(|_| no_upstream())?;
+
+    let rejected = RefCell::new(None);
+    let mut remote = raw.find_remote(&remote_name)?;
+    let mut callbacks = callbacks(raw.config()?);
+    callbacks.push_update_reference(|_, status| {
+        if let Some(msg) = status {
+            *rejected.borrow_mut() = Some(msg.to_string());
+        }
+        Ok(())
+    });
+    let mut opts = PushOptions::new();
+    opts.remote_callbacks(callbacks);
+    remote.push(&[format!("{}:{}", branch, merge)], Some(&mut opts))?;
+    drop(opts);
+    match rejected.into_inner() {
+        Some(msg) => Err(RgmError {
+            message: format!("Push rejected: {}", msg),
+        }),
+        None => Ok(format!("Pushed {} to {}", short, remote_name)),
+    }
+}
+
+fn checkout(raw: &Repository, name: &str) -> Result<String> {
+    let local = match raw.find_branch(name, BranchType::Local) {
+        Ok(branch) => branch,
+        Err(_) => {
+            // `origin/feature`, or `feature` on any remote
+            let remote_branch = raw.find_branch(name, BranchType::Remote).ok().or_else(|| {
+                raw.remotes().ok()?.iter().flatten().find_map(|remote| {
+                    raw.find_branch(&format!("{}/{}", remote, name), BranchType::Remote)
+                        .ok()
+                })
+            });
+            let remote_branch = remote_branch.ok_or_else(|| RgmError {
+                message: format!("No branch {}", name),
+            })?;
+            let remote_name = remote_branch.name()?.unwrap_or_default().to_string();
+            let local_name = remote_name.split_once('/').map(|(_, b)| b).unwrap_or(name);
+            let commit = remote_branch.get().peel_to_commit()?;
+            let mut local = raw.branch(local_name, &commit, false)?;
+            local.set_upstream(Some(&remote_name))?;
+            local
+        }
+    };
+    let refname = local.get().name().unwrap_or_default().to_string();
+    let target = local.get().peel(git2::ObjectType::Commit)?;
+    raw.checkout_tree(&target, Some(CheckoutBuilder::new().safe()))?;
+    raw.set_head(&refname)?;
+    Ok(format!("Switched to {}", refname.trim_start_matches("refs/heads/")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::{commit_file, test_dir};
+    use std::fs;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_ops() {
+        let root = test_dir("ops");
+        let origin = Repository::init_bare(root.join("origin.git")).unwrap();
+        let url = root.join("origin.git");
+        let url = url.to_str().unwrap();
+        let one = Repository::clone(url, root.join("one")).unwrap();
+        commit_file(&one, "a");
+        let branch = one.head().unwrap().shorthand().unwrap().to_string();
+        // The first push sets up the upstream by hand, like `git push -u` would
+        one.find_remote("origin")
+            .unwrap()
+            .push(&[format!("refs/heads/{}", branch)], None)
+            .unwrap();
+        one.find_branch(&branch, BranchType::Local)
+            .unwrap()
+            .set_upstream(Some(&format!("origin/{}", branch)))
+            .unwrap();
+
+        let two = Repository::clone(url, root.join("two")).unwrap();
+        commit_file(&one, "b");
+        assert!(GitOp::Push.run(&root.join("one")).is_ok());
+        assert_eq!(
+            GitOp::Pull.run(&root.join("two")).unwrap(),
+            "Fast-forwarded 1 commits"
+        );
+        assert!(root.join("two/b").exists());
+        assert_eq!(GitOp::Pull.run(&root.join("two")).unwrap(), "Already up to date");
+
+        // Diverged, push is rejected and pull can't fast-forward
+        commit_file(&one, "c");
+        GitOp::Push.run(&root.join("one")).unwrap();
+        commit_file(&two, "d");
+        assert!(GitOp::Push.run(&root.join("two")).is_err());
+        assert!(GitOp::Pull.run(&root.join("two")).is_err());
+
+        // Checkout of a branch that only exists on the remote
+        let head = origin.head().unwrap().peel_to_commit().unwrap();
+        origin.branch("feature", &head, false).unwrap();
+        GitOp::Fetch.run(&root.join("two")).unwrap();
+        assert_eq!(
+            GitOp::Checkout(String::from("feature")).run(&root.join("two")).unwrap(),
+            "Switched to feature"
+        );
+        assert_eq!(two.head().unwrap().shorthand(), Some("feature"));
+        assert!(GitOp::Checkout(String::from("nope")).run(&root.join("two")).is_err());
+
+        // Every remote is tried, a broken one fails the fetch
+        two.remote("mirror", url).unwrap();
+        assert!(GitOp::FetchAll.run(&root.join("two")).unwrap().starts_with("Fetched 2 remotes"));
+        two.remote("broken", root.join("missing.git").to_str().unwrap()).unwrap();
+        let err = GitOp::FetchAll.run(&root.join("two")).unwrap_err();
+        assert!(err.message.starts_with("broken: "));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_fetch_timeout() {
+        let root = test_dir("fetch-timeout");
+        let raw = Repository::init(&root).unwrap();
+        // Accepts the connection but never answers
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/repo.git", server.local_addr().unwrap());
+        raw.remote("origin", &url).unwrap();
+        let started = Instant::now();
+        let err = fetch_remote_within(&raw, "origin", Duration::from_secs(1)).unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_abandoned_fetch_writes_nothing() {
+        let root = test_dir("fetch-abandoned");
+        let origin = Repository::init(root.join("origin")).unwrap();
+        commit_file(&origin, "a");
+        let url = root.join("origin");
+        let deadline = Instant::now() + FETCH_TIMEOUT;
+        for abandoned in [true, false] {
+            let raw = Repository::init(root.join(format!("clone-{}", abandoned))).unwrap();
+            raw.remote("origin", url.to_str().unwrap()).unwrap();
+            let (tx, rx) = mpsc::channel();
+            fetch_until(raw.path(), "origin", deadline, &Mutex::new(abandoned), tx);
+            let fetched = raw.references_glob("refs/remotes/origin/*").unwrap().count() > 0;
+            assert_eq!(fetched, !abandoned);
+            assert_eq!(raw.path().join("FETCH_HEAD").exists(), !abandoned);
+            match rx.try_recv() {
+                Ok(res) => assert!(!abandoned && res.unwrap() > 0),
+                Err(_) => assert!(abandoned),
+            }
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(GitOp::parse("pull", &["--ff-only"]).unwrap(), GitOp::Pull);
+        assert!(GitOp::parse("pull", &["--rebase"]).is_err());
+        assert_eq!(GitOp::parse("fetch", &["--all"]).unwrap(), GitOp::FetchAll);
+        assert_eq!(
+            GitOp::parse("co", &["main"]).unwrap(),
+            GitOp::Checkout(String::from("main"))
+        );
+        assert!(GitOp::parse("checkout", &[]).is_err());
+    }
+}
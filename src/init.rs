@@ -0,0 +1,176 @@
+// Shell integration printed by `rgm init <shell>`
+use crate::args::ShellType;
+use crate::complete;
+
+/// Every shell's integration follows this layout, the parts are filled in per shell
+const TEMPLATE: &str = "\
+# Welcome to RGM!
+#
+# The wrapper gives rgm-bin a temporary file of its own in $RGM_OUTPUT, rgm-bin
+# writes a script to it for the wrapper to run once it exits. That is how it
+# changes the directory of this shell, without clashing with rgm in other shells.
+
+{wrapper}
+{completion}
+{bindings}
+# To init rgm, add the following line to {rc}:
+#
+# {setup}
+";
+
+/// Shell specific parts of `TEMPLATE`
+struct Parts {
+    // Function running rgm-bin, then the script it left in $RGM_OUTPUT
+    wrapper: &'static str,
+    // Ctrl-G opens rgm
+    bindings: &'static str,
+    rc: &'static str,
+    setup: &'static str,
+}
+
+// The script is read and the file removed before running it, it may start a nix shell
+const POSIX_WRAPPER: &str = r#"rgm() {
+    __rgm_output=$(mktemp "${TMPDIR:-/tmp}/rgm.XXXXXX") || return
+    RGM_SHELL={shell} RGM_OUTPUT="$__rgm_output" rgm-bin "$@"
+    __rgm_status=$?
+    __rgm_script=$(cat "$__rgm_output")
+    rm -f "$__rgm_output"
+    unset __rgm_output
+    eval "$__rgm_script"
+    set -- "$__rgm_status"
+    unset __rgm_status __rgm_script
+    return "$1"
+}
+"#;
+
+fn parts(shell: &ShellType) -> Parts {
+    match shell {
+        ShellType::Sh => Parts {
+            wrapper: POSIX_WRAPPER,
+            bindings: "# POSIX sh has no key bindings\n",
+            rc: "$HOME/.profile (or the file $ENV points to)",
+            setup: r#"eval "$(rgm-bin init sh)""#,
+        },
+        ShellType::Bash => Parts {
+            wrapper: POSIX_WRAPPER,
+            bindings: r#"bind -x '"\C-g": rgm'
+"#,
+            rc: "$HOME/.bashrc",
+            setup: r#"eval "$(rgm-bin init bash --bindings)""#,
+        },
+        ShellType::Zsh => Parts {
+            wrapper: POSIX_WRAPPER,
+            bindings: r#"_rgm_widget() {
+    rgm </dev/tty
+    zle reset-prompt
+}
+zle -N _rgm_widget
+bindkey '^G' _rgm_widget
+"#,
+            rc: "$HOME/.zshrc (after compinit)",
+            setup: r#"eval "$(rgm-bin init zsh --bindings)""#,
+        },
+        ShellType::Fish => Parts {
+            wrapper: r#"function rgm
+    set -l output (mktemp -t rgm.XXXXXX)
+    or return
+    RGM_SHELL=fish RGM_OUTPUT=$output rgm-bin $argv
+    set -l rgm_status $status
+    set -l script (cat $output | string collect)
+    rm -f $output
+    test -n "$script"
+    and eval $script
+    return $rgm_status
+end
+"#,
+            bindings: r#"bind \cg 'rgm; commandline -f repaint'
+"#,
+            rc: "$HOME/.config/fish/config.fish",
+            setup: "rgm-bin init fish --bindings | source",
+        },
+        // Nushell can't source a file chosen at runtime, so rgm-bin leaves a JSON record with
+        // the directory and the commands to run instead of a script
+        ShellType::Nu => Parts {
+            wrapper: r#"def --env --wrapped rgm [...args: string@"nu-complete rgm"] {
+    let output = (mktemp --tmpdir rgm.XXXXXX)
+    # A failing rgm-bin raises an error, catch it so the file is always removed
+    let status = try {
+        with-env { RGM_SHELL: nu, RGM_OUTPUT: $output } {
+            ^rgm-bin ...$args
+            $env.LAST_EXIT_CODE
+        }
+    } catch {|err| $err.exit_code? | default 1 }
+    let script = (open --raw $output | str trim)
+    rm -f $output
+    if $script != "" {
+        let enter = ($script | from json)
+        cd $enter.cd
+        for cmd in $enter.run { ^nu -c $cmd }
+    }
+    if $status != 0 {
+        error make --unspanned { msg: $"rgm-bin exited with status ($status)" }
+    }
+}
+"#,
+            bindings: r#"$env.config.keybindings = ($env.config.keybindings | append {
+    name: rgm
+    modifier: control
+    keycode: char_g
+    mode: [emacs vi_insert vi_normal]
+    event: { send: executehostcommand cmd: "rgm" }
+})
+"#,
+            rc: "$nu.config-path, after generating it with `rgm-bin init nu --bindings | save -f ~/.rgm/init.nu`",
+            setup: "source ~/.rgm/init.nu",
+        },
+    }
+}
+
+fn name(shell: &ShellType) -> &'static str {
+    match shell {
+        ShellType::Sh => "sh",
+        ShellType::Bash => "bash",
+        ShellType::Zsh => "zsh",
+        ShellType::Fish => "fish",
+        ShellType::Nu => "nu",
+    }
+}
+
+/// Integration for `shell`, with the Ctrl-G binding only when asked for
+pub fn init_script(shell: &ShellType, bindings: bool) -> String {
+    let parts = parts(shell);
+    let bindings = if bindings { parts.bindings } else { "" };
+    TEMPLATE
+        .replace("{wrapper}", &parts.wrapper.replace("{shell}", name(shell)))
+        .replace("{completion}", &complete::completions(shell))
+        .replace("{bindings}", bindings)
+        .replace("{rc}", parts.rc)
+        .replace("{setup}", parts.setup)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_init_script() {
+        let zsh = init_script(&ShellType::Zsh, false);
+        assert!(zsh.contains("RGM_SHELL=zsh RGM_OUTPUT=\"$__rgm_output\" rgm-bin \"$@\""));
+        assert!(zsh.contains("compdef _rgm_complete rgm"));
+        assert!(!zsh.contains("bindkey"));
+        assert!(init_script(&ShellType::Zsh, true).contains("bindkey '^G' _rgm_widget"));
+        assert!(init_script(&ShellType::Sh, true).contains("RGM_SHELL=sh RGM_OUTPUT="));
+        assert!(init_script(&ShellType::Fish, true).contains("bind \\cg"));
+        let nu = init_script(&ShellType::Nu, false);
+        assert!(nu.contains("def --env --wrapped rgm"));
+        // The temp file is removed before the exit status is passed on
+        assert!(nu.find("rm -f $output").unwrap() < nu.find("error make").unwrap());
+        assert!(!init_script(&ShellType::Fish, false).contains("rgm.sh"));
+        for shell in [ShellType::Sh, ShellType::Bash, ShellType::Zsh, ShellType::Fish, ShellType::Nu] {
+            let script = init_script(&shell, true);
+            for placeholder in ["{wrapper}", "{completion}", "{bindings}", "{shell}", "{rc}"] {
+                assert!(!script.contains(placeholder), "{} left in {:?}", placeholder, shell);
+            }
+        }
+    }
+}
@@ -39,12 +39,20 @@ impl Input {
         }
     }
 
+    pub fn info(msg: String) -> Self {
+        Self {
+            editing: false,
+            text: msg,
+            status: InputStatus::Info,
+        }
+    }
+
     pub fn push(&mut self, v: char) {
         self.text.push(v);
     }
 
     pub fn pop(&mut self) {
-        // Leave command prompt
+        // Leave command (or search) prompt
         if self.text.len() > 1 {
             self.text.pop();
         }
@@ -59,6 +67,13 @@ impl Input {
         }
     }
 
+    /// Start editing with another prompt than `:`, e.g. `/` for search
+    pub fn prompt(&mut self, prompt: char) {
+        self.editing(true);
+        self.text = prompt.to_string();
+        self.status = InputStatus::Info;
+    }
+
     pub fn is_editing(&self) -> bool {
         self.editing
     }
@@ -114,14 +129,14 @@ mod tests {
         def.editing(true);
         def.push('a');
         def.push('b');
-        assert_eq!(def.is_editing(), true);
+        assert!(def.is_editing());
         assert_eq!(def.text(), String::from(":ab"));
         // pop doesn't remove `:` prompt
         def.pop();
         def.pop();
         def.pop();
         def.editing(false);
-        assert_eq!(def.is_editing(), false);
+        assert!(!def.is_editing());
         assert_eq!(def.text(), String::from(":"));
         // Clear removes text
         def.clear();
@@ -0,0 +1,163 @@
+// Non-interactive output of the stored repos, `rgm list`
+use crossterm::{
+    style::{Color as TermColor, Stylize},
+    tty::IsTty,
+};
+use std::io::{self, Write};
+use tui::style::Color;
+
+use crate::repo::Repo;
+use crate::repoitem::get_color_for_status;
+
+const HEADER: [&str; 6] = ["NAME", "ALIAS", "BRANCH", "STATUS", "TAGS", "PATH"];
+
+/// Cells of a repo's row, in the order of `HEADER`
+fn row(repo: &Repo) -> [String; 6] {
+    [
+        repo.name.clone(),
+        repo.alias.clone().unwrap_or_default(),
+        repo.branch.clone(),
+        repo.status
+            .as_ref()
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| String::from("Unknown")),
+        repo.tags.join(","),
+        repo.path.display().to_string(),
+    ]
+}
+
+/// Cells of the table with the header first, and the width of each column
+fn table(repos: &[&Repo]) -> (Vec<[String; 6]>, [usize; 6]) {
+    let mut rows = vec![HEADER.map(String::from)];
+    rows.extend(repos.iter().map(|r| row(r)));
+    let mut widths = [0; 6];
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    (rows, widths)
+}
+
+/// Same colours the TUI uses, as the crossterm backend of tui maps them
+fn term_color(color: Color) -> TermColor {
+    match color {
+        Color::Red => TermColor::DarkRed,
+        Color::Green => TermColor::DarkGreen,
+        Color::Yellow => TermColor::DarkYellow,
+        Color::Blue => TermColor::DarkBlue,
+        Color::Magenta => TermColor::DarkMagenta,
+        Color::Cyan => TermColor::DarkCyan,
+        Color::Gray => TermColor::Grey,
+        Color::DarkGray => TermColor::DarkGrey,
+        Color::LightRed => TermColor::Red,
+        Color::LightGreen => TermColor::Green,
+        Color::LightYellow => TermColor::Yellow,
+        Color::LightBlue => TermColor::Blue,
+        Color::LightMagenta => TermColor::Magenta,
+        Color::LightCyan => TermColor::Cyan,
+        Color::Black => TermColor::Black,
+        Color::Rgb(r, g, b) => TermColor::Rgb { r, g, b },
+        Color::Indexed(i) => TermColor::AnsiValue(i),
+        Color::White => TermColor::White,
+        Color::Reset => TermColor::Reset,
+    }
+}
+
+/// Pad every cell but the last to the width of its column
+fn format_row(cells: &[String; 6], widths: &[usize; 6]) -> Vec<String> {
+    cells
+        .iter()
+        .zip(widths.iter())
+        .enumerate()
+        .map(|(i, (cell, width))| match i {
+            // No trailing spaces after the last column
+            5 => cell.clone(),
+            _ => format!("{:width$}", cell, width = width),
+        })
+        .collect()
+}
+
+/// Print an aligned table, colouring the status when writing to a terminal
+pub fn print_table(repos: &[&Repo]) {
+    let color = io::stdout().is_tty();
+    let (rows, widths) = table(repos);
+    let mut lines = rows.iter().map(|cells| format_row(cells, &widths));
+    let mut out = io::stdout().lock();
+    if let Some(header) = lines.next() {
+        let header = header.join("  ");
+        let res = if color {
+            writeln!(out, "{}", header.bold())
+        } else {
+            writeln!(out, "{}", header)
+        };
+        // The reader went away, e.g. `rgm list | head`
+        if res.is_err() {
+            return;
+        }
+    }
+    for (repo, mut cells) in repos.iter().zip(lines) {
+        if let Some(status) = repo.status.as_ref().filter(|_| color) {
+            cells[3] = cells[3]
+                .clone()
+                .with(term_color(get_color_for_status(status)))
+                .to_string();
+        }
+        if writeln!(out, "{}", cells.join("  ")).is_err() {
+            return;
+        }
+    }
+}
+
+/// Print the repos as a JSON array, in the same layout as the store
+pub fn print_json(repos: &[&Repo]) {
+    let mut out = io::stdout().lock();
+    if serde_json::to_writer_pretty(&mut out, repos).is_ok() {
+        let _ = writeln!(out);
+    }
+}
+
+/// Print just the paths, one per line, for piping into other tools
+pub fn print_paths(repos: &[&Repo]) {
+    let mut out = io::stdout().lock();
+    for repo in repos {
+        if writeln!(out, "{}", repo.path.display()).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repo::{Changes, Status, Tracking};
+    use crate::testutil::repo;
+
+    #[test]
+    fn test_table() {
+        let api = Repo {
+            alias: Some(String::from("a")),
+            status: Some(Status {
+                changes: Changes::default(),
+                tracking: Tracking::Even,
+                upstream: Some(String::from("origin/main")),
+                operation: None,
+            }),
+            ..repo("api", "/src/api", &["rust", "backend"])
+        };
+        let web = Repo {
+            branch: String::from("develop"),
+            ..repo("web-frontend", "/src/web-frontend", &[])
+        };
+        let (rows, widths) = table(&[&api, &web]);
+        let lines: Vec<String> = rows.iter().map(|r| format_row(r, &widths).join("  ")).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "NAME          ALIAS  BRANCH   STATUS                              TAGS          PATH",
+                "api           a      main     Clean, up to date with origin/main  rust,backend  /src/api",
+                "web-frontend         develop  Unknown                                           /src/web-frontend",
+            ]
+        );
+    }
+}
@@ -1,36 +1,48 @@
-use args::{Cli, Commands, ShellType};
+use args::{Cli, Commands};
 use clap::Parser;
 use logging::setup_log;
-use log::{info, error};
-use std::{io, fs};
+use log::error;
+use std::{io, fs, process};
 
-use crate::repo::Repos;
+use crate::query::{Query, Resolved};
+use crate::repo::{Repo, Repos};
 use crate::screen::Screen;
+use crate::shell::Shell;
+use crate::sorting::{Sort, SortOrder};
 
 mod args;
+mod batch;
+mod complete;
 mod error;
+mod exec;
+mod filter;
+mod fuzzy;
+mod gitops;
+mod init;
 mod input;
+mod list;
 mod logging;
+mod migrate;
+mod pool;
+mod query;
 mod repo;
 mod repoitem;
 mod repoview;
 mod screen;
+mod shell;
 pub mod sorting;
+mod store;
+#[cfg(test)]
+mod testutil;
 mod utils;
 
-fn usage() {
-    println!("rgm PATH")
-}
-
-
 fn main() {
     setup_log().unwrap();
     log::info!("Set up logging");
-    utils::clear_shell_file();
     let cli = Cli::parse();
     match cli.command {
         Some(command) => match command {
-            Commands::Tag { mut tags, path } => {
+            Commands::Tag { tags, path } => {
                 let path = match fs::canonicalize(path) {
                     Ok(p) => p,
                     Err(e) => {
@@ -43,14 +55,11 @@ fn main() {
                 let mut applied = 0;
                 for r in repos.repos.iter_mut() {
                     // if r.path is a subdirectory of path
-                    if r.path.starts_with(&path) {
-                        if r.add_tags(&mut tags){
-                            applied += 1;
-                        }
+                    if r.path.starts_with(&path) && r.add_tags(&tags) {
+                        applied += 1;
                     }
                 }
                 println!("Applied tags to {} repos, saving", applied);
-                println!("{:?}", repos);
                 if let Err(e) = repos.save(){
                     println!("{:?}", e);
                 }
@@ -69,49 +78,191 @@ fn main() {
                     println!("{:?}", e);
                 }
             }
-            Commands::Import { path } => {
-                let repos = Repos::from_dir(&path);
+            Commands::Import { path, prune } => {
+                let path = match fs::canonicalize(path) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        println!("Could not parse input {:?}", e);
+                        return
+                    }
+                };
+                // Start a new store on first import, but never overwrite one we can't read
+                let mut repos = if utils::config_file().exists() {
+                    utils::get_repos_or_exit()
+                } else {
+                    Repos::default()
+                };
+                let summary = repos.import(&path, prune);
+                println!("Imported {}: {}", path.display(), summary);
                 match repos.save() {
                     Ok(p) => println!("Saved {} repos to {}", &repos.meta.size, p.display()),
                     Err(e) => println!("Error saving repos: {}", e),
                 }
             },
-            Commands::Update { path } => {
+            Commands::Update { path, jobs, fetch } => {
                 let repos = Repos::load();
                 match repos {
-                    // TODO progress bar for updating
                     Ok(mut r) => {
-                        if let Some(p) = path {
-                            // TODO: Update only paths in given directory
-                            unimplemented!()
+                        let jobs = jobs.unwrap_or_else(pool::default_jobs);
+                        let summary = if let Some(p) = path {
+                            let p = match fs::canonicalize(p) {
+                                Ok(p) => p,
+                                Err(e) => {
+                                    println!("Could not parse input {:?}", e);
+                                    return
+                                }
+                            };
+                            r.update_dir(&p, jobs, fetch, utils::print_update_progress)
                         } else {
-                            r.update();
-                        }
+                            r.update(jobs, fetch, utils::print_update_progress)
+                        };
+                        println!("{}", summary);
                     },
-                    Err(e) => error!("{:?}", e),
+                    Err(e) => {
+                        error!("{:?}", e);
+                        println!("Could not load repos: {}", e);
+                    }
                 }
             },
-            Commands::Init { shell } => {
-                match shell {
-                    ShellType::Zsh => utils::zsh_init(),
-                    ShellType::Bash => utils::bash_init(),
-                    _ => {}
+            Commands::List { query, sort, reverse, tags, json, paths } => {
+                let query = match Query::parse(&query.join(" ")) {
+                    Ok(q) => q,
+                    Err(e) => {
+                        println!("{}", e);
+                        process::exit(1)
+                    }
+                };
+                let repos = utils::read_repos_or_exit();
+                let mut order = match sort.as_deref().map(SortOrder::parse) {
+                    Some(Ok(order)) => order,
+                    Some(Err(e)) => {
+                        println!("{}", e);
+                        process::exit(1)
+                    }
+                    None => repos.settings.sort.clone(),
+                };
+                order.reverse ^= reverse;
+                let repos = order.sort(repos);
+                let matched: Vec<&Repo> = repos
+                    .repos
+                    .iter()
+                    .filter(|r| tags.iter().all(|t| r.tags.contains(t)) && query.matches(r))
+                    .collect();
+                if json {
+                    list::print_json(&matched);
+                } else if paths {
+                    list::print_paths(&matched);
+                } else {
+                    list::print_table(&matched);
+                }
+            },
+            Commands::Exec { tags, path, query, all, jobs, group, command } => {
+                let path = match path.map(fs::canonicalize).transpose() {
+                    Ok(p) => p,
+                    Err(e) => {
+                        println!("Could not parse input {:?}", e);
+                        process::exit(1)
+                    }
+                };
+                let query = match query.as_deref().map(Query::parse).transpose() {
+                    Ok(q) => q,
+                    Err(e) => {
+                        println!("{}", e);
+                        process::exit(1)
+                    }
+                };
+                let selection = exec::Selection { tags, path, query };
+                if selection.is_empty() && !all {
+                    println!("Select repos with --tag, --path or --query, or use --all");
+                    process::exit(1)
+                }
+                let repos = utils::read_repos_or_exit();
+                let selected: Vec<&Repo> = repos.repos.iter().filter(|r| selection.matches(r)).collect();
+                if selected.is_empty() {
+                    println!("No repos selected");
+                    process::exit(1)
+                }
+                let output = if group { exec::Output::Group } else { exec::Output::Prefix };
+                let summary = exec::run(&selected, &command, jobs.unwrap_or_else(pool::default_jobs), output);
+                println!("{}", summary);
+                if !summary.failed().is_empty() {
+                    process::exit(1)
+                }
+            },
+            Commands::Go { query, print } => {
+                let input = query.join(" ");
+                let mut repos = utils::get_repos_or_exit();
+                let resolved = match query::resolve(&input, &repos.repos) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        println!("{}", e);
+                        process::exit(1)
+                    }
+                };
+                match resolved {
+                    Resolved::One(i) => {
+                        let repo = &mut repos.repos[i];
+                        repo.enter();
+                        let path = repo.path.clone();
+                        let script = shell::enter_script(Shell::current(), repo, repos.settings.enter);
+                        if let Err(e) = repos.save() {
+                            error!("{:?}", e);
+                        }
+                        if print {
+                            println!("{}", path.display());
+                        } else if let Err(e) = shell::write_script(&script) {
+                            // Still say where it is, so it can be reached by hand
+                            error!("{:?}", e);
+                            println!("{}\n{}", path.display(), e);
+                            process::exit(1)
+                        }
+                    }
+                    Resolved::Ambiguous(matches) if print => {
+                        println!("{} repos match {}:", matches.len(), input);
+                        let width = matches.iter().map(|i| repos.repos[*i].name.len()).max().unwrap_or(0);
+                        for i in matches {
+                            let r = &repos.repos[i];
+                            println!("  {:width$}  {}", r.name, r.path.display(), width = width);
+                        }
+                        process::exit(1)
+                    }
+                    Resolved::Ambiguous(_) => run_tui(repos, Some(&input)),
+                    Resolved::NotFound => {
+                        println!("No repo matches {}", input);
+                        process::exit(1)
+                    }
+                }
+            },
+            Commands::Init { shell, bindings } => print!("{}", init::init_script(&shell, bindings)),
+            Commands::Completions { shell } => print!("{}", complete::completions(&shell)),
+            Commands::Complete { words } => {
+                for candidate in complete::candidates(&words) {
+                    println!("{}", candidate);
                 }
             }
         },
         None => {
             let repos = Repos::load();
             match repos {
-                Ok(r) => {
-                    //r.update();
-                    let mut screen = Screen::new(r);
-                    let mut out = io::stdout();
-                    if let Err(e) = screen.run(out) {
-                        error!("{:?}", e)
-                    }
+                Ok(r) => run_tui(r, None),
+                Err(e) => {
+                    error!("{:?}", e);
+                    println!("Could not load repos: {}", e);
                 }
-                Err(e) => error!("{:?}", e),
             }
         }
     }
 }
+
+/// Open the TUI, optionally starting with a filter applied
+fn run_tui(mut repos: Repos, filter: Option<&str>) {
+    // The TUI can stay open for a long time, don't block other rgm processes
+    repos.unlock();
+    let mut screen = Screen::new(repos);
+    if let Some(query) = filter {
+        screen.filter(query);
+    }
+    if let Err(e) = screen.run(io::stdout()) {
+        error!("{:?}", e)
+    }
+}
@@ -0,0 +1,141 @@
+// Upgrades of older store formats to the current one
+use serde_json::{json, Value};
+
+use crate::error::{Result, RgmError};
+
+/// Current version of the store format, bump it and add a migration whenever the on disk layout
+/// of `Repos` changes in a way serde defaults can't handle.
+pub const VERSION: u32 = 2;
+
+type Migration = fn(&mut Value) -> Result<()>;
+
+/// `MIGRATIONS[n]` upgrades a version `n` store to version `n + 1`
+const MIGRATIONS: [Migration; VERSION as usize] = [v0_to_v1, v1_to_v2];
+
+/// Format version of a raw store, stores from before versioning have none.
+pub fn version(store: &Value) -> u32 {
+    store["meta"]["version"].as_u64().unwrap_or(0) as u32
+}
+
+/// Upgrade `store` in place to the current version, returns the version it started at.
+pub fn migrate(store: &mut Value) -> Result<u32> {
+    let from = version(store);
+    if from > VERSION {
+        return Err(RgmError {
+            message: format!(
+                "Store has format version {} but this rgm only supports up to {}, please upgrade rgm",
+                from, VERSION
+            ),
+        });
+    }
+    for (to, migration) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+        migration(store)?;
+        store["meta"]["version"] = json!(to + 1);
+    }
+    Ok(from)
+}
+
+fn meta_mut(store: &mut Value) -> Result<&mut serde_json::Map<String, Value>> {
+    store
+        .get_mut("meta")
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| RgmError {
+            message: String::from("Store has no meta section"),
+        })
+}
+
+/// Version 0 had no format version or save generation
+fn v0_to_v1(store: &mut Value) -> Result<()> {
+    let meta = meta_mut(store)?;
+    meta.entry("generation").or_insert(json!(0));
+    Ok(())
+}
+
+/// Version 1 had a single status value compared against `origin/<branch>`, it becomes the
+/// composite status with change counts and upstream tracking.
+///
+/// The old `Dirty` (and `Other`) carry no counts, so they are dropped and filled in by the next
+/// `rgm update`.
+fn v1_to_v2(store: &mut Value) -> Result<()> {
+    let repos = match store.get_mut("repos").and_then(Value::as_array_mut) {
+        Some(repos) => repos,
+        None => return Ok(()),
+    };
+    for repo in repos.iter_mut() {
+        let upstream = repo["branch"].as_str().map(|b| format!("origin/{}", b));
+        let tracking = match &repo["status"] {
+            Value::String(s) if s == "Clean" => json!("Even"),
+            Value::String(s) if s == "Bare" => json!("Bare"),
+            Value::String(s) if s == "Detached" => json!("Detached"),
+            Value::Object(o) if o.contains_key("Diverged") => {
+                let ahead = o["Diverged"][0].as_u64().unwrap_or(0);
+                let behind = o["Diverged"][1].as_u64().unwrap_or(0);
+                match (ahead, behind) {
+                    (0, 0) => json!("Even"),
+                    (a, 0) => json!({ "Ahead": a }),
+                    (0, b) => json!({ "Behind": b }),
+                    (a, b) => json!({ "Diverged": [a, b] }),
+                }
+            }
+            _ => Value::Null,
+        };
+        repo["status"] = match tracking {
+            Value::Null => Value::Null,
+            tracking => {
+                let upstream = match tracking.as_str() {
+                    Some("Bare") | Some("Detached") => None,
+                    _ => upstream,
+                };
+                json!({ "changes": {}, "tracking": tracking, "upstream": upstream })
+            }
+        };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repo::{Repos, Tracking};
+
+    fn load_fixture(contents: &str) -> (Repos, u32) {
+        let mut store: Value = serde_json::from_str(contents).unwrap();
+        let from = migrate(&mut store).unwrap();
+        assert_eq!(version(&store), VERSION);
+        (serde_json::from_value(store).unwrap(), from)
+    }
+
+    #[test]
+    fn test_load_v0() {
+        let (repos, from) = load_fixture(include_str!("../tests/fixtures/store_v0.json"));
+        assert_eq!(from, 0);
+        assert_eq!(repos.meta.version, VERSION);
+        assert_eq!(repos.meta.generation, 0);
+        assert_eq!(repos.repos.len(), 3);
+        assert_eq!(repos.repos[0].alias, Some(String::from("a")));
+        // Dirty had no counts to migrate
+        assert_eq!(repos.repos[0].status, None);
+        let status = repos.repos[1].status.as_ref().unwrap();
+        assert_eq!(status.tracking, Tracking::Ahead(2));
+        assert_eq!(status.upstream.as_deref(), Some("origin/develop"));
+        assert!(status.changes.is_clean());
+    }
+
+    #[test]
+    fn test_load_v1() {
+        let (repos, from) = load_fixture(include_str!("../tests/fixtures/store_v1.json"));
+        assert_eq!(from, 1);
+        assert_eq!(repos.meta.generation, 7);
+        assert_eq!(repos.repos[1].status.as_ref().unwrap().tracking, Tracking::Ahead(2));
+        // Missing fields get their defaults
+        assert_eq!(repos.repos[2].alias, None);
+        assert!(repos.repos[2].tags.is_empty());
+        assert!(repos.repos[2].remotes.is_empty());
+    }
+
+    #[test]
+    fn test_newer_version() {
+        let mut store = json!({"repos": [], "meta": {"size": 0, "version": VERSION + 1}});
+        assert!(migrate(&mut store).is_err());
+    }
+}
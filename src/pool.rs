@@ -0,0 +1,75 @@
+// Bounded worker pool for running jobs over many repos at once
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+
+/// Upper bound on the default number of workers, most of the work is disk bound
+const MAX_DEFAULT_JOBS: usize = 8;
+
+/// Default number of workers, based on the available parallelism.
+pub fn default_jobs() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(MAX_DEFAULT_JOBS)
+}
+
+/// Run `job` on every item using at most `jobs` threads.
+///
+/// `on_done` is called on the calling thread as each job finishes (in completion order, not
+/// input order), so it can be used to report progress without any locking.
+pub fn run<T, R, J, D>(items: Vec<T>, jobs: usize, job: J, mut on_done: D)
+where
+    T: Send,
+    R: Send,
+    J: Fn(T) -> R + Sync,
+    D: FnMut(R),
+{
+    let workers = jobs.max(1).min(items.len());
+    let queue = Mutex::new(items.into_iter());
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let queue = &queue;
+            let job = &job;
+            scope.spawn(move || loop {
+                // Only hold the lock long enough to take the next item
+                let next = queue.lock().unwrap().next();
+                match next {
+                    Some(item) => {
+                        if tx.send(job(item)).is_err() {
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            });
+        }
+        // Drop our sender so the receiver finishes once every worker is done
+        drop(tx);
+        for res in rx {
+            on_done(res);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run_all_items() {
+        let mut results = Vec::new();
+        run((0..20).collect(), 4, |i: usize| i * 2, |r| results.push(r));
+        results.sort_unstable();
+        assert_eq!(results, (0..20).map(|i| i * 2).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_run_empty() {
+        let mut called = false;
+        run(Vec::<usize>::new(), 4, |i| i, |_| called = true);
+        assert!(!called);
+    }
+}
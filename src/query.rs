@@ -0,0 +1,529 @@
+// Query language for selecting repos, e.g. `tag:backend status:dirty (branch:main OR name~api)`
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::error::{Result, RgmError};
+use crate::fuzzy::{fuzzy_match, Match};
+use crate::repo::{QueryOpts, Repo};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    // Bare words, a substring of the name, alias or a tag. With fuzzy matching on the name and
+    // alias are fuzzy matched instead.
+    Any,
+    Name,
+    Alias,
+    Tag,
+    Branch,
+    Status,
+    Path,
+    // Stashes, submodules and worktrees, e.g. `has:stashes`
+    Has,
+}
+
+impl FromStr for Field {
+    type Err = RgmError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "name" => Ok(Field::Name),
+            "alias" => Ok(Field::Alias),
+            "tag" => Ok(Field::Tag),
+            "branch" => Ok(Field::Branch),
+            "status" => Ok(Field::Status),
+            "path" => Ok(Field::Path),
+            "has" => Ok(Field::Has),
+            _ => Err(RgmError {
+                message: format!("Unknown field '{}'", s),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    // `field:value`, exact match (or under the directory for paths)
+    Is,
+    // `field~value`, substring match
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Term {
+    field: Field,
+    op: Op,
+    // Lowercased except for paths, all other matching is case insensitive
+    value: String,
+}
+
+impl Term {
+    fn matches(&self, repo: &Repo, fuzzy: bool) -> bool {
+        match (self.field, self.op) {
+            (Field::Any, _) if fuzzy => {
+                best_match(&self.value, repo).is_some() || repo.query(&self.value, QueryOpts::Tags)
+            }
+            (Field::Any, _) => repo.query(&self.value, QueryOpts::Any),
+            (Field::Name, Op::Contains) => repo.query(&self.value, QueryOpts::Name),
+            (Field::Alias, Op::Contains) => repo.query(&self.value, QueryOpts::Alias),
+            (Field::Tag, Op::Contains) => repo.query(&self.value, QueryOpts::Tags),
+            (Field::Name, Op::Is) => repo.name.to_lowercase() == self.value,
+            (Field::Alias, Op::Is) => repo
+                .alias
+                .as_ref()
+                .map(|a| a.to_lowercase() == self.value)
+                .unwrap_or(false),
+            (Field::Tag, Op::Is) => repo.tags.iter().any(|t| t.to_lowercase() == self.value),
+            (Field::Branch, op) => compare(&repo.branch, &self.value, op),
+            (Field::Status, op) => match &repo.status {
+                Some(status) => status.labels().iter().any(|l| compare(l, &self.value, op)),
+                None => compare("unknown", &self.value, op),
+            },
+            (Field::Has, Op::Is) => repo.has_labels().iter().any(|l| {
+                // Singular works too, `has:stash`
+                *l == self.value
+                    || l.strip_suffix('s') == Some(&self.value)
+                    || l.strip_suffix("es") == Some(&self.value)
+            }),
+            (Field::Has, Op::Contains) => repo.has_labels().iter().any(|l| l.contains(&self.value)),
+            (Field::Path, Op::Is) => repo.path.starts_with(expand_home(&self.value)),
+            (Field::Path, Op::Contains) => repo.path.to_string_lossy().contains(&self.value),
+        }
+    }
+}
+
+/// Best fuzzy match of `pattern` against the repo name or alias, and whether it was the name
+pub fn best_match(pattern: &str, repo: &Repo) -> Option<(Match, bool)> {
+    let name = fuzzy_match(pattern, &repo.name).map(|m| (m, true));
+    let alias = repo
+        .alias
+        .as_ref()
+        .and_then(|a| fuzzy_match(pattern, a))
+        .map(|m| (m, false));
+    match (name, alias) {
+        (Some(n), Some(a)) => Some(if a.0.score > n.0.score { a } else { n }),
+        (n, a) => n.or(a),
+    }
+}
+
+/// What `rgm go <input>` points at, by index into the repos
+#[derive(Debug, PartialEq, Eq)]
+pub enum Resolved {
+    One(usize),
+    // Several matches, best first
+    Ambiguous(Vec<usize>),
+    NotFound,
+}
+
+/// Find the repo `input` refers to: an exact alias first, then an exact name, then whatever
+/// the input matches as a query.
+pub fn resolve(input: &str, repos: &[Repo]) -> Result<Resolved> {
+    let input = input.trim();
+    let exact = |by_alias: bool| -> Vec<usize> {
+        repos
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| {
+                if by_alias {
+                    r.alias.as_deref() == Some(input)
+                } else {
+                    r.name == input
+                }
+            })
+            .map(|(i, _)| i)
+            .collect()
+    };
+    for by_alias in [true, false] {
+        match exact(by_alias).as_slice() {
+            [] => {}
+            [i] => return Ok(Resolved::One(*i)),
+            many => return Ok(Resolved::Ambiguous(many.to_vec())),
+        }
+    }
+
+    let query = Query::parse(input)?;
+    let terms = query.fuzzy_terms();
+    // Fuzzy, so `rgm go` gets there with as few keystrokes as possible
+    let mut matched: Vec<(i64, usize)> = repos
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| query.matches_fuzzy(r))
+        .map(|(i, r)| {
+            let score = terms
+                .iter()
+                .filter_map(|t| best_match(t, r))
+                .map(|(m, _)| m.score)
+                .sum();
+            (score, i)
+        })
+        .collect();
+    matched.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    Ok(match matched.as_slice() {
+        [] => Resolved::NotFound,
+        [(_, i)] => Resolved::One(*i),
+        _ => Resolved::Ambiguous(matched.into_iter().map(|(_, i)| i).collect()),
+    })
+}
+
+fn compare(field: &str, value: &str, op: Op) -> bool {
+    let field = field.to_lowercase();
+    match op {
+        Op::Is => field == value,
+        Op::Contains => field.contains(value),
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest.trim_start_matches('/')),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// A parsed query, evaluated against each repo with `matches`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    // Empty query
+    All,
+    Term(Term),
+    Not(Box<Query>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Self> {
+        let tokens = tokenize(input)?;
+        if tokens.is_empty() {
+            return Ok(Query::All);
+        }
+        let mut parser = Parser { tokens, pos: 0 };
+        let query = parser.or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some(t) => Err(parse_error(format!("Unexpected '{}'", t))),
+        }
+    }
+
+    /// Bare words that aren't negated, used for ranking and highlighting fuzzy matches
+    pub fn fuzzy_terms(&self) -> Vec<&str> {
+        match self {
+            Query::Term(t) if t.field == Field::Any => vec![t.value.as_str()],
+            Query::And(qs) | Query::Or(qs) => qs.iter().flat_map(|q| q.fuzzy_terms()).collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn matches(&self, repo: &Repo) -> bool {
+        self.eval(repo, false)
+    }
+
+    /// Like `matches`, but bare words fuzzy match the name and alias
+    pub fn matches_fuzzy(&self, repo: &Repo) -> bool {
+        self.eval(repo, true)
+    }
+
+    fn eval(&self, repo: &Repo, fuzzy: bool) -> bool {
+        match self {
+            Query::All => true,
+            Query::Term(t) => t.matches(repo, fuzzy),
+            Query::Not(q) => !q.eval(repo, fuzzy),
+            Query::And(qs) => qs.iter().all(|q| q.eval(repo, fuzzy)),
+            Query::Or(qs) => qs.iter().any(|q| q.eval(repo, fuzzy)),
+        }
+    }
+}
+
+fn parse_error(msg: String) -> RgmError {
+    RgmError {
+        message: format!("Invalid query: {}", msg),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Word(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+            Token::And => write!(f, "AND"),
+            Token::Or => write!(f, "OR"),
+            Token::Not => write!(f, "NOT"),
+            Token::Word(w) => write!(f, "{}", w),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '!' | '-' => {
+                chars.next();
+                tokens.push(Token::Not);
+            }
+            '&' | '|' => {
+                chars.next();
+                // Allow both `&` and `&&`
+                if chars.peek() == Some(&c) {
+                    chars.next();
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            _ => {
+                let mut word = String::new();
+                let mut quoted = false;
+                while let Some(&c) = chars.peek() {
+                    if !quoted && (c.is_whitespace() || c == '(' || c == ')') {
+                        break;
+                    }
+                    chars.next();
+                    if c == '"' {
+                        quoted = !quoted;
+                    } else {
+                        word.push(c);
+                    }
+                }
+                if quoted {
+                    return Err(parse_error(String::from("Unterminated quote")));
+                }
+                tokens.push(match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_term(word: &str) -> Result<Term> {
+    let split = word.find([':', '~']);
+    let (field, op, value) = match split {
+        Some(i) => {
+            let op = if word[i..].starts_with(':') { Op::Is } else { Op::Contains };
+            (word[..i].parse::<Field>()?, op, &word[i + 1..])
+        }
+        None => (Field::Any, Op::Contains, word),
+    };
+    if value.is_empty() {
+        return Err(parse_error(format!("Missing value for '{}'", word)));
+    }
+    let value = match field {
+        Field::Path => value.to_string(),
+        _ => value.to_lowercase(),
+    };
+    Ok(Term { field, op, value })
+}
+
+// Precedence, lowest first: OR, AND (explicit or implied by whitespace), NOT
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Query> {
+        let mut queries = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            queries.push(self.and()?);
+        }
+        Ok(if queries.len() == 1 { queries.remove(0) } else { Query::Or(queries) })
+    }
+
+    fn and(&mut self) -> Result<Query> {
+        let mut queries = vec![self.not()?];
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next();
+                }
+                Some(Token::Open) | Some(Token::Not) | Some(Token::Word(_)) => {}
+                _ => break,
+            }
+            queries.push(self.not()?);
+        }
+        Ok(if queries.len() == 1 { queries.remove(0) } else { Query::And(queries) })
+    }
+
+    fn not(&mut self) -> Result<Query> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Query::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Query> {
+        match self.next() {
+            Some(Token::Open) => {
+                let query = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(query),
+                    _ => Err(parse_error(String::from("Missing ')'"))),
+                }
+            }
+            Some(Token::Word(w)) => Ok(Query::Term(parse_term(&w)?)),
+            Some(t) => Err(parse_error(format!("Unexpected '{}'", t))),
+            None => Err(parse_error(String::from("Unexpected end of query"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repo::{Changes, Operation, Status, Tracking};
+    use crate::testutil;
+
+    fn status(modified: usize, tracking: Tracking) -> Status {
+        Status {
+            changes: Changes {
+                modified,
+                ..Changes::default()
+            },
+            tracking,
+            upstream: Some(String::from("origin/main")),
+            operation: None,
+        }
+    }
+
+    fn repo(name: &str, path: &str, tags: &[&str], status: Status) -> Repo {
+        Repo {
+            status: Some(status),
+            ..testutil::repo(name, path, tags)
+        }
+    }
+
+    fn matching(query: &str, repos: &[Repo]) -> Vec<String> {
+        let query = Query::parse(query).unwrap();
+        repos
+            .iter()
+            .filter(|r| query.matches(r))
+            .map(|r| r.name.clone())
+            .collect()
+    }
+
+    fn fuzzy_matching(query: &str, repos: &[Repo]) -> Vec<String> {
+        let query = Query::parse(query).unwrap();
+        repos
+            .iter()
+            .filter(|r| query.matches_fuzzy(r))
+            .map(|r| r.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_query_eval() {
+        let mut repos = vec![
+            repo("api", "/work/infra/api", &["backend"], status(2, Tracking::Behind(1))),
+            repo("web-api", "/work/web", &["frontend"], status(0, Tracking::Even)),
+            repo("infra", "/work/infra/infra", &["backend", "ops"], status(0, Tracking::Diverged(1, 3))),
+        ];
+        assert_eq!(matching("", &repos).len(), 3);
+        assert_eq!(matching("api", &repos), vec!["api", "web-api"]);
+        assert!(matching("wpi", &repos).is_empty());
+        assert_eq!(fuzzy_matching("wpi", &repos), vec!["web-api"]);
+        assert_eq!(matching("ops", &repos), vec!["infra"]);
+        assert_eq!(fuzzy_matching("ops", &repos), vec!["infra"]);
+        assert_eq!(matching("name:api", &repos), vec!["api"]);
+        assert_eq!(matching("tag:backend status:clean", &repos), vec!["infra"]);
+        assert_eq!(matching("tag:frontend OR status:dirty", &repos), vec!["api", "web-api"]);
+        assert_eq!(matching("path:/work/infra !name~api", &repos), vec!["infra"]);
+        assert_eq!(matching("NOT (tag:ops | Name:WEB-API)", &repos), vec!["api"]);
+        assert_eq!(matching("tag~end && -status:dirty", &repos), vec!["web-api", "infra"]);
+        assert_eq!(matching("status:behind", &repos), vec!["api", "infra"]);
+        assert_eq!(matching("status:dirty status:behind", &repos), vec!["api"]);
+        repos[2].status.as_mut().unwrap().operation = Some(Operation::CherryPicking);
+        assert_eq!(matching("status:cherry-picking", &repos), vec!["infra"]);
+        assert_eq!(matching("status:inprogress OR status:detached", &repos), vec!["infra"]);
+        repos[0].stashes = 2;
+        assert_eq!(matching("has:stashes", &repos), vec!["api"]);
+        assert_eq!(matching("has:stash OR has~submodule", &repos), vec!["api"]);
+    }
+
+    #[test]
+    fn test_path_case() {
+        let repos = vec![repo("app", "/Users/Me/App", &[], status(0, Tracking::Even))];
+        assert_eq!(matching("path:/Users/Me", &repos), vec!["app"]);
+        assert_eq!(matching("path~Me/App", &repos), vec!["app"]);
+        assert!(matching("path:/users/me", &repos).is_empty());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let clean = || status(0, Tracking::Even);
+        let mut repos = vec![
+            repo("api-server", "/src/api-server", &["backend"], clean()),
+            repo("web-api", "/src/web-api", &["frontend"], clean()),
+            repo("infra", "/src/infra", &["ops"], clean()),
+            repo("api", "/src/old/api", &[], clean()),
+        ];
+        repos[2].alias = Some(String::from("api"));
+        // An alias beats a name, which beats fuzzy matches
+        assert_eq!(resolve("api", &repos).unwrap(), Resolved::One(2));
+        assert_eq!(resolve("web-api", &repos).unwrap(), Resolved::One(1));
+        assert_eq!(resolve("wapi", &repos).unwrap(), Resolved::One(1));
+        assert_eq!(resolve("ap tag:backend", &repos).unwrap(), Resolved::One(0));
+        assert_eq!(resolve("nothing", &repos).unwrap(), Resolved::NotFound);
+        assert_eq!(resolve("pi", &repos).unwrap(), Resolved::Ambiguous(vec![0, 1, 2, 3]));
+        assert!(resolve("tag:", &repos).is_err());
+    }
+
+    #[test]
+    fn test_fuzzy_terms() {
+        let query = Query::parse("api (tag:a OR srv) -old").unwrap();
+        assert_eq!(query.fuzzy_terms(), vec!["api", "srv"]);
+    }
+
+    #[test]
+    fn test_query_parse() {
+        assert_eq!(
+            Query::parse(r#"name:"my repo""#).unwrap(),
+            Query::Term(Term {
+                field: Field::Name,
+                op: Op::Is,
+                value: String::from("my repo")
+            })
+        );
+        assert!(Query::parse("colour:red").is_err());
+        assert!(Query::parse("(tag:a").is_err());
+        assert!(Query::parse("tag:").is_err());
+        assert!(Query::parse("tag:a OR").is_err());
+        assert!(Query::parse(r#"name:"a"#).is_err());
+    }
+}
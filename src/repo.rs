@@ -1,13 +1,27 @@
-use git2::{Error as GitError, ErrorCode, Repository, StatusOptions};
+use git2::{
+    Error as GitError, ErrorCode, Repository, RepositoryState, StatusOptions, SubmoduleIgnore,
+    SubmoduleStatus,
+};
 use serde::{Deserialize, Serialize};
-use std::fs::OpenOptions;
-use std::io::prelude::*;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
-use log::{error, info};
+use log::{debug, error, info, warn};
 
-use crate::utils::config_file;
+use crate::utils::{self, config_file};
 use crate::error::{Result, RgmError};
+use crate::gitops::GitOp;
+use crate::migrate;
+use crate::pool;
+use crate::shell::EnterAction;
+use crate::sorting::SortOrder;
+use crate::store;
+
+/// Repos with remotes that weren't fetched for this long are shown as stale, in seconds
+pub const STALE_AFTER: u64 = 24 * 60 * 60;
 
 pub enum QueryOpts {
     Name,
@@ -16,38 +30,244 @@ pub enum QueryOpts {
     Any,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum Status {
-    //Bare repo,
-    Bare,
-    // Commits diverged from main remote (origin) TODO: customize main remote?
+/// Number of files in each kind of uncommitted change, a file can be both staged and modified
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Changes {
+    pub staged: usize,
+    pub modified: usize,
+    pub untracked: usize,
+    pub conflicted: usize,
+}
+
+impl Changes {
+    pub fn is_clean(&self) -> bool {
+        *self == Changes::default()
+    }
+}
+
+impl fmt::Display for Changes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_clean() {
+            return write!(f, "Clean");
+        }
+        let counts: Vec<String> = [
+            (self.conflicted, "conflicted"),
+            (self.staged, "staged"),
+            (self.modified, "modified"),
+            (self.untracked, "untracked"),
+        ]
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, kind)| format!("{} {}", n, kind))
+        .collect();
+        write!(f, "Dirty ({})", counts.join(", "))
+    }
+}
+
+/// Where the current branch stands compared to its upstream
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Tracking {
+    // Same commit as the upstream
+    Even,
+    Ahead(usize),
+    Behind(usize),
     // Ahead, Behind
     Diverged(usize, usize),
-    // Even, and nothing modified
-    Clean,
-    // Modified work
-    Dirty,
-    // In a head detached state
+    // The branch doesn't track anything
+    NoUpstream,
+    // The branch tracks an upstream that no longer exists
+    UpstreamGone,
+    // Not on a branch
     Detached,
-    // TODO: Others? Merge/rebase in progress?
-    Other,
+    // On a branch without any commits yet
+    Unborn,
+    // No work dir or branch at all
+    Bare,
 }
 
-impl Status {
-    pub fn display(&self) -> &str {
+impl Tracking {
+    fn from_ahead_behind(ahead: usize, behind: usize) -> Self {
+        match (ahead, behind) {
+            (0, 0) => Tracking::Even,
+            (ahead, 0) => Tracking::Ahead(ahead),
+            (0, behind) => Tracking::Behind(behind),
+            (ahead, behind) => Tracking::Diverged(ahead, behind),
+        }
+    }
+}
+
+/// Multi step git operation that was started but not finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operation {
+    Merging,
+    Rebasing,
+    CherryPicking,
+    Reverting,
+    Bisecting,
+    // `git am`
+    ApplyingPatches,
+}
+
+impl Operation {
+    fn from_state(state: RepositoryState) -> Option<Self> {
+        match state {
+            RepositoryState::Clean => None,
+            RepositoryState::Merge => Some(Operation::Merging),
+            RepositoryState::Rebase
+            | RepositoryState::RebaseInteractive
+            | RepositoryState::RebaseMerge
+            | RepositoryState::ApplyMailboxOrRebase => Some(Operation::Rebasing),
+            RepositoryState::CherryPick | RepositoryState::CherryPickSequence => {
+                Some(Operation::CherryPicking)
+            }
+            RepositoryState::Revert | RepositoryState::RevertSequence => Some(Operation::Reverting),
+            RepositoryState::Bisect => Some(Operation::Bisecting),
+            RepositoryState::ApplyMailbox => Some(Operation::ApplyingPatches),
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
         match self {
-            Status::Bare => "Empty",
-            // Todo
-            Status::Diverged(_, _) => "Diverged",
-            Status::Clean => "Clean",
-            Status::Dirty => "Dirty",
-            Status::Detached => "Detached",
-            Status::Other => "Other",
+            Operation::Merging => "merging",
+            Operation::Rebasing => "rebasing",
+            Operation::CherryPicking => "cherry-picking",
+            Operation::Reverting => "reverting",
+            Operation::Bisecting => "bisecting",
+            Operation::ApplyingPatches => "applying",
+        }
+    }
+}
+
+/// Working tree changes and upstream state of a repo
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Status {
+    pub changes: Changes,
+    pub tracking: Tracking,
+    // Short name of the upstream, e.g. `origin/main`
+    pub upstream: Option<String>,
+    // Merge, rebase etc. left half way
+    #[serde(default)]
+    pub operation: Option<Operation>,
+}
+
+impl Status {
+    /// Lowercase names for the parts of the status, what `status:` queries match against
+    pub fn labels(&self) -> Vec<&'static str> {
+        let mut labels = vec![if self.changes.is_clean() { "clean" } else { "dirty" }];
+        if let Some(operation) = self.operation {
+            labels.extend(["inprogress", operation.label()]);
+        }
+        let counts = [
+            (self.changes.staged, "staged"),
+            (self.changes.modified, "modified"),
+            (self.changes.untracked, "untracked"),
+            (self.changes.conflicted, "conflicted"),
+        ];
+        labels.extend(counts.iter().filter(|(n, _)| *n > 0).map(|(_, l)| *l));
+        match self.tracking {
+            Tracking::Even => labels.push("even"),
+            Tracking::Ahead(_) => labels.push("ahead"),
+            Tracking::Behind(_) => labels.push("behind"),
+            Tracking::Diverged(_, _) => labels.extend(["diverged", "ahead", "behind"]),
+            Tracking::NoUpstream => labels.push("noupstream"),
+            Tracking::UpstreamGone => labels.push("gone"),
+            Tracking::Detached => labels.push("detached"),
+            Tracking::Unborn => labels.push("unborn"),
+            Tracking::Bare => labels = vec!["bare"],
+        }
+        labels
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.tracking == Tracking::Bare {
+            return write!(f, "Bare");
+        }
+        if let Some(operation) = self.operation {
+            let label = operation.label();
+            write!(f, "{}{}, ", label[..1].to_uppercase(), &label[1..])?;
+        }
+        write!(f, "{}", self.changes)?;
+        let upstream = self.upstream.as_deref().unwrap_or("upstream");
+        match self.tracking {
+            Tracking::Even => write!(f, ", up to date with {}", upstream),
+            Tracking::Ahead(n) => write!(f, ", {} ahead of {}", n, upstream),
+            Tracking::Behind(n) => write!(f, ", {} behind {}", n, upstream),
+            Tracking::Diverged(ahead, behind) => write!(
+                f,
+                ", diverged from {} ({} ahead, {} behind)",
+                upstream, ahead, behind
+            ),
+            Tracking::NoUpstream => write!(f, ", no upstream"),
+            Tracking::UpstreamGone => write!(f, ", {} is gone", upstream),
+            Tracking::Detached => write!(f, ", detached"),
+            Tracking::Unborn => write!(f, ", no commits yet"),
+            Tracking::Bare => Ok(()),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The commit HEAD points at
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitInfo {
+    pub summary: String,
+    pub author: String,
+    // Commit time, seconds since the epoch
+    pub time: u64,
+}
+
+/// Submodule of a repo, by its path relative to the repo
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Submodule {
+    pub path: PathBuf,
+    // Not checked out, checked out at another commit than recorded, or with local changes.
+    // None if git couldn't read its status.
+    pub dirty: Option<bool>,
+}
+
+/// What the user set on a repo, as opposed to what is read from git
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserData {
+    pub alias: Option<String>,
+    pub tags: Vec<String>,
+    pub on_enter: Option<String>,
+}
+
+fn describe(value: &Option<String>) -> String {
+    value
+        .as_ref()
+        .map(|v| format!("'{}'", v))
+        .unwrap_or_else(|| String::from("none"))
+}
+
+/// Three way merge of a single valued field, see `Repo::merge_user_data`
+fn merge_field(
+    field: &str,
+    name: &str,
+    theirs: &mut Option<String>,
+    ours: Option<String>,
+    base: &Option<String>,
+    dropped: &mut Vec<String>,
+) {
+    if ours == *base {
+        return;
+    }
+    if theirs != base && *theirs != ours {
+        dropped.push(format!(
+            "{}: replaced the {} {} set by another rgm process with yours, {}",
+            name,
+            field,
+            describe(theirs),
+            describe(&ours)
+        ));
+    }
+    *theirs = ours;
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Repo {
     // Path to repo work dir
     pub path: PathBuf,
@@ -63,6 +283,20 @@ pub struct Repo {
     pub alias: Option<String>,
     // List of tags
     pub tags: Vec<String>,
+    // When the repo was last entered through rgm, seconds since the epoch
+    pub last_entered: Option<u64>,
+    // Number of stash entries
+    pub stashes: usize,
+    pub submodules: Vec<Submodule>,
+    // Paths of the linked worktrees
+    pub worktrees: Vec<PathBuf>,
+    pub last_commit: Option<CommitInfo>,
+    // Latest modification of the index or a changed file, seconds since the epoch
+    pub worktree_modified: Option<u64>,
+    // When any remote was last fetched (by rgm or git), seconds since the epoch
+    pub last_fetch: Option<u64>,
+    // Shell command run after entering the repo through rgm
+    pub on_enter: Option<String>,
 }
 
 impl Repo {
@@ -83,13 +317,14 @@ impl Repo {
             remotes,
             alias,
             tags,
+            ..Self::default()
         }
     }
 
-    pub fn add_tags(&mut self, add_tags: &Vec<String>) -> bool {
+    pub fn add_tags(&mut self, add_tags: &[String]) -> bool {
         let mut added = false;
         for tag in add_tags.iter() {
-            if !self.tags.contains(&tag) {
+            if !self.tags.contains(tag) {
                 added = true;
                 self.tags.push(tag.clone())
             }
@@ -101,153 +336,785 @@ impl Repo {
         self.alias = Some(alias)
     }
 
+    /// Record that the repo was just entered
+    pub fn enter(&mut self) {
+        self.last_entered = Some(utils::now());
+    }
+
+    /// Carry over the user supplied data (alias, tags, hook) from a previous entry of this repo.
+    pub fn inherit(&mut self, old: Repo) {
+        self.alias = old.alias;
+        self.tags = old.tags;
+        self.last_entered = old.last_entered;
+        self.on_enter = old.on_enter;
+    }
+
+    /// Take what was read from git from `other`, a copy of this repo that was refreshed
+    fn take_git_state(&mut self, other: &mut Repo) {
+        self.branch = mem::take(&mut other.branch);
+        self.status = other.status.take();
+        self.remotes = mem::take(&mut other.remotes);
+        self.stashes = other.stashes;
+        self.submodules = mem::take(&mut other.submodules);
+        self.worktrees = mem::take(&mut other.worktrees);
+        self.last_commit = other.last_commit.take();
+        self.worktree_modified = other.worktree_modified;
+        self.last_fetch = other.last_fetch;
+    }
+
+    pub fn user_data(&self) -> UserData {
+        UserData {
+            alias: self.alias.clone(),
+            tags: self.tags.clone(),
+            on_enter: self.on_enter.clone(),
+        }
+    }
+
+    /// Merge `ours`, our copy of this repo, into this one, the copy another process saved
+    /// since we loaded `base`.
+    ///
+    /// Whatever we changed since `base` wins, also over their change to the same field, and
+    /// what we left alone takes their value. Tags are merged one by one, so a tag removed on
+    /// either side stays removed. Returns their changes that ours replaced.
+    pub fn merge_user_data(&mut self, ours: Repo, base: &UserData) -> Vec<String> {
+        let mut dropped = Vec::new();
+        let name = &self.name;
+        merge_field("alias", name, &mut self.alias, ours.alias, &base.alias, &mut dropped);
+        let on_enter = &mut self.on_enter;
+        merge_field("hook", name, on_enter, ours.on_enter, &base.on_enter, &mut dropped);
+        self.tags.retain(|t| ours.tags.contains(t) || !base.tags.contains(t));
+        let added: Vec<String> = ours.tags.into_iter().filter(|t| !base.tags.contains(t)).collect();
+        self.add_tags(&added);
+        self.last_entered = self.last_entered.max(ours.last_entered);
+        dropped
+    }
+
+    /// Case insensitive substring match of `query_str` against the fields given by `opts`.
     pub fn query(&self, query_str: &str, opts: QueryOpts) -> bool {
+        let query_str = query_str.to_lowercase();
+        let matches = |field: &str| field.to_lowercase().contains(&query_str);
         match opts {
-            QueryOpts::Name => self.name.contains(query_str),
-            _ => unimplemented!(),
+            QueryOpts::Name => matches(&self.name),
+            QueryOpts::Tags => self.tags.iter().any(|t| matches(t)),
+            QueryOpts::Alias => self.alias.as_deref().map(matches).unwrap_or(false),
+            QueryOpts::Any => {
+                matches(&self.name)
+                    || self.alias.as_deref().map(matches).unwrap_or(false)
+                    || self.tags.iter().any(|t| matches(t))
+            }
         }
     }
 
-    pub fn update(&mut self) {
-        let raw = match Repository::open(&self.path.as_path()){
-            Ok(r) => r,
-            Err(msg) => {
-                error!("{:?}", msg);
-                return
-            }
-        };
-        self.status = get_status(&raw);
+    /// Whether the repo has each kind of leftover work, what `has:` queries match against
+    pub fn has_labels(&self) -> Vec<&'static str> {
+        let mut labels = Vec::new();
+        if self.stashes > 0 {
+            labels.push("stashes");
+        }
+        if !self.submodules.is_empty() {
+            labels.push("submodules");
+        }
+        if self.submodules.iter().any(|s| s.dirty == Some(true)) {
+            labels.push("dirty-submodules");
+        }
+        if !self.worktrees.is_empty() {
+            labels.push("worktrees");
+        }
+        labels
+    }
+
+    /// Most recent time anything happened in the repo: a commit, a change to the work tree, or
+    /// entering it through rgm.
+    pub fn last_activity(&self) -> Option<u64> {
+        let commit = self.last_commit.as_ref().map(|c| c.time);
+        commit.max(self.worktree_modified).max(self.last_entered)
+    }
+
+    /// Whether ahead/behind may be out of date because the remotes weren't fetched for a while
+    pub fn is_stale(&self) -> bool {
+        if self.remotes.is_empty() {
+            return false;
+        }
+        match self.last_fetch {
+            Some(time) => utils::now().saturating_sub(time) > STALE_AFTER,
+            None => true,
+        }
+    }
+
+    /// Fetch every remote of the repo, `update` picks up the new ahead/behind afterwards
+    pub fn fetch(&self) -> Result<String> {
+        GitOp::FetchAll.run(&self.path)
+    }
+
+    /// Refresh branch, status, remotes, last commit, stashes, submodules and worktrees from
+    /// disk, returns whether the status changed.
+    pub fn update(&mut self) -> Result<bool> {
+        let raw = Repository::open(self.path.as_path()).map_err(|err| RgmError {
+            message: err.message().to_string(),
+        })?;
+        if let Some(branch) = branch_name(&raw) {
+            self.branch = branch;
+        }
+        let (status, modified) = get_status(&raw);
+        let changed = status != self.status;
+        self.status = status;
+        self.worktree_modified = modified;
+        self.last_commit = get_last_commit(&raw);
+        self.last_fetch = get_last_fetch(&raw);
         self.remotes = match raw.remotes() {
             Ok(remotes) => remotes.iter()
-                .map(|x| x.unwrap().to_string())
+                .flatten()
+                .map(|x| x.to_string())
                 .collect(),
             Err(_) => Vec::new()
-        }
+        };
+        self.read_work(&raw);
+        Ok(changed)
+    }
+
+    /// Read the stashes, submodules and worktrees, which hold work outside the current branch
+    fn read_work(&mut self, raw: &Repository) {
+        // Each stash entry is an entry in the stash reflog
+        self.stashes = raw.reflog("refs/stash").map(|r| r.len()).unwrap_or(0);
+        self.submodules = match raw.submodules() {
+            Ok(submodules) => submodules
+                .iter()
+                .map(|s| {
+                    let path = s.path().to_path_buf();
+                    // Unknown rather than dirty when git can't tell
+                    let status = s.name().map(|name| raw.submodule_status(name, SubmoduleIgnore::Untracked));
+                    let dirty = match status {
+                        Some(Ok(status)) => Some(status.intersects(submodule_dirty())),
+                        Some(Err(e)) => {
+                            warn!("Could not read submodule {} of {}: {}", path.display(), self.name, e);
+                            None
+                        }
+                        None => {
+                            warn!("Submodule {} of {} has no utf-8 name", path.display(), self.name);
+                            None
+                        }
+                    };
+                    Submodule { path, dirty }
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        self.worktrees = match raw.worktrees() {
+            Ok(names) => names
+                .iter()
+                .flatten()
+                .filter_map(|name| raw.find_worktree(name).ok())
+                .map(|w| w.path().to_path_buf())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
     }
 
     pub fn from_raw(raw: Repository) -> std::result::Result<Self, GitError> {
-        let head = raw.head()?;
-        let rev = head.shorthand().unwrap();
-        
-        let status = get_status(&raw);
+        let rev = branch_name(&raw).ok_or_else(|| GitError::from_str("HEAD is missing"))?;
+        let (status, worktree_modified) = get_status(&raw);
         let repo_path = raw.workdir().unwrap().to_path_buf();
         let name = String::from(repo_path.as_path().file_name().unwrap().to_str().unwrap());
-        Ok(Repo {
+        let mut repo = Repo {
             path: repo_path,
-            name: name,
-            branch: rev.to_string(),
-            status: status,
+            name,
+            branch: rev,
+            status,
             remotes: raw
                 .remotes()?
                 .iter()
                 .map(|x| x.unwrap().to_string())
                 .collect(),
-            alias: None,
-            tags: vec![],
-        })
+            last_commit: get_last_commit(&raw),
+            last_fetch: get_last_fetch(&raw),
+            worktree_modified,
+            ..Repo::default()
+        };
+        repo.read_work(&raw);
+        Ok(repo)
     }
 }
 
-fn get_status(raw: &Repository) -> Option<Status> {
-    let mut stat_opts = StatusOptions::new();
-    let status = match raw.statuses(Some(&mut stat_opts)) {
-        Ok(status_raw) => {
-            // TODO: determine between clean, detached, behind, ahead
-            if status_raw.is_empty() {
-                local_remote_diff(&raw, "origin").ok()
-            } else {
-                Some(Status::Dirty)
-            }
+/// Submodule states that mean it doesn't match what the parent repo recorded
+fn submodule_dirty() -> SubmoduleStatus {
+    SubmoduleStatus::INDEX_ADDED
+        | SubmoduleStatus::INDEX_DELETED
+        | SubmoduleStatus::INDEX_MODIFIED
+        | SubmoduleStatus::WD_UNINITIALIZED
+        | SubmoduleStatus::WD_ADDED
+        | SubmoduleStatus::WD_DELETED
+        | SubmoduleStatus::WD_MODIFIED
+        | SubmoduleStatus::WD_INDEX_MODIFIED
+        | SubmoduleStatus::WD_WD_MODIFIED
+}
+
+/// Name of the checked out branch, which exists before its first commit too
+fn branch_name(raw: &Repository) -> Option<String> {
+    match raw.head() {
+        Ok(head) => head.shorthand().map(String::from),
+        Err(e) if e.code() == ErrorCode::UnbornBranch => raw
+            .find_reference("HEAD")
+            .ok()?
+            .symbolic_target()
+            .map(|target| String::from(short_ref(target))),
+        Err(_) => None,
+    }
+}
+
+fn get_last_commit(raw: &Repository) -> Option<CommitInfo> {
+    let commit = raw.head().ok()?.peel_to_commit().ok()?;
+    let author = commit.author();
+    Some(CommitInfo {
+        summary: commit.summary().unwrap_or_default().to_string(),
+        author: author.name().unwrap_or_default().to_string(),
+        time: u64::try_from(commit.time().seconds()).unwrap_or(0),
+    })
+}
+
+/// Every fetch rewrites FETCH_HEAD, whichever tool did it
+fn get_last_fetch(raw: &Repository) -> Option<u64> {
+    mtime(&raw.path().join("FETCH_HEAD"))
+}
+
+/// Modification time of `path` in seconds since the epoch
+fn mtime(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+/// Status of the repo, and when its work tree was last modified
+fn get_status(raw: &Repository) -> (Option<Status>, Option<u64>) {
+    let (changes, modified) = match get_changes(raw) {
+        Ok(changes) => changes,
+        Err(e) if e.code() == ErrorCode::BareRepo => {
+            let status = Status {
+                changes: Changes::default(),
+                tracking: Tracking::Bare,
+                upstream: None,
+                operation: None,
+            };
+            return (Some(status), None);
         }
         Err(e) => {
-            if e.code() == ErrorCode::BareRepo {
-                Some(Status::Bare)
-            } else {
-                None
-            }
+            debug!("Could not get status of {:?}: {}", raw.path(), e);
+            return (None, None);
+        }
+    };
+    let (tracking, upstream) = match get_tracking(raw) {
+        Ok(tracking) => tracking,
+        Err(e) => {
+            debug!("Could not compare {:?} to its upstream: {}", raw.path(), e);
+            return (None, modified);
         }
     };
-    status
-}
-
-fn local_remote_diff(
-    repo: &Repository,
-    remote: &str,
-) -> std::result::Result<Status, Box<dyn std::error::Error>> {
-    // Get local head
-    let head = repo.head()?;
-    let local_head = head.peel_to_commit()?;
-    let remote = format!("{}/{}", remote, head.shorthand().unwrap());
-    // Get remote head
-    let remote_head = repo
-        .resolve_reference_from_short_name(&remote)?
-        .peel_to_commit()?;
-    // Get diff with `repo.graph_ahead_behind(local, remote)`
-    let (behind, ahead) = repo.graph_ahead_behind(local_head.id(), remote_head.id())?;
-    // Set Status:
-    if (behind, ahead) == (0, 0) {
-        Ok(Status::Clean)
-    } else {
-        Ok(Status::Diverged(behind, ahead))
-    }
-}
-
-#[derive(Deserialize, Serialize, Debug)]
+    let status = Status {
+        changes,
+        tracking,
+        upstream,
+        operation: Operation::from_state(raw.state()),
+    };
+    (Some(status), modified)
+}
+
+/// Count the changes in the work tree, along with the latest modification time of the index
+/// and the changed files. Unchanged files are left out, stat'ing the whole tree is too slow.
+fn get_changes(raw: &Repository) -> std::result::Result<(Changes, Option<u64>), GitError> {
+    let mut stat_opts = StatusOptions::new();
+    stat_opts
+        .include_untracked(true)
+        .include_ignored(false)
+        .exclude_submodules(true);
+    let staged = git2::Status::INDEX_NEW
+        | git2::Status::INDEX_MODIFIED
+        | git2::Status::INDEX_DELETED
+        | git2::Status::INDEX_RENAMED
+        | git2::Status::INDEX_TYPECHANGE;
+    let modified = git2::Status::WT_MODIFIED
+        | git2::Status::WT_DELETED
+        | git2::Status::WT_RENAMED
+        | git2::Status::WT_TYPECHANGE;
+    let mut changes = Changes::default();
+    let workdir = raw.workdir().unwrap_or_else(|| raw.path());
+    let mut modified_at = mtime(&raw.path().join("index"));
+    for entry in raw.statuses(Some(&mut stat_opts))?.iter() {
+        if let Some(path) = entry.path() {
+            modified_at = modified_at.max(mtime(&workdir.join(path)));
+        }
+        let status = entry.status();
+        if status.is_conflicted() {
+            changes.conflicted += 1;
+            continue;
+        }
+        if status.intersects(staged) {
+            changes.staged += 1;
+        }
+        if status.intersects(modified) {
+            changes.modified += 1;
+        }
+        if status.is_wt_new() {
+            changes.untracked += 1;
+        }
+    }
+    Ok((changes, modified_at))
+}
+
+/// `refs/remotes/origin/main` -> `origin/main`
+fn short_ref(name: &str) -> &str {
+    name.strip_prefix("refs/remotes/")
+        .or_else(|| name.strip_prefix("refs/heads/"))
+        .unwrap_or(name)
+}
+
+/// Compare the current branch to the upstream configured for it, along with the upstream name
+fn get_tracking(raw: &Repository) -> std::result::Result<(Tracking, Option<String>), GitError> {
+    if raw.head_detached()? {
+        return Ok((Tracking::Detached, None));
+    }
+    let head = match raw.head() {
+        Ok(head) => head,
+        Err(e) if e.code() == ErrorCode::UnbornBranch => return Ok((Tracking::Unborn, None)),
+        Err(e) => return Err(e),
+    };
+    let branch = head.name().unwrap_or_default();
+    let upstream_name = match raw.branch_upstream_name(branch) {
+        Ok(name) => String::from(short_ref(name.as_str().unwrap_or_default())),
+        Err(e) if e.code() == ErrorCode::NotFound => return Ok((Tracking::NoUpstream, None)),
+        Err(e) => return Err(e),
+    };
+    let upstream = match raw.resolve_reference_from_short_name(&upstream_name) {
+        Ok(upstream) => upstream,
+        Err(e) if e.code() == ErrorCode::NotFound => {
+            return Ok((Tracking::UpstreamGone, Some(upstream_name)))
+        }
+        Err(e) => return Err(e),
+    };
+    let local = head.peel_to_commit()?;
+    let upstream_head = upstream.peel_to_commit()?;
+    let (ahead, behind) = raw.graph_ahead_behind(local.id(), upstream_head.id())?;
+    Ok((Tracking::from_ahead_behind(ahead, behind), Some(upstream_name)))
+}
+
+/// Outcome of refreshing a single repo
+#[derive(Debug)]
+pub enum UpdateOutcome {
+    Unchanged,
+    // Status before and after the refresh
+    Changed(Option<Status>, Option<Status>),
+    Failed(RgmError),
+}
+
+/// Reported once per repo as `Repos::update` makes progress
+pub struct UpdateProgress<'a> {
+    pub done: usize,
+    pub total: usize,
+    pub name: &'a str,
+    pub outcome: &'a UpdateOutcome,
+    // Set when fetching was asked for and failed, the status is still refreshed
+    pub fetch_error: Option<&'a RgmError>,
+}
+
+#[derive(Debug, Default)]
+pub struct UpdateSummary {
+    pub refreshed: usize,
+    pub changed: usize,
+    // Names of the repos that couldn't be opened
+    pub failed: Vec<String>,
+    pub fetched: usize,
+    // Names of the repos whose remotes couldn't all be fetched
+    pub fetch_failed: Vec<String>,
+    // Newly found repos
+    pub added: usize,
+    // Repos whose work dir no longer exists
+    pub removed: usize,
+}
+
+impl fmt::Display for UpdateSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Refreshed {} repos, {} changed status, {} failed to open",
+            self.refreshed,
+            self.changed,
+            self.failed.len()
+        )?;
+        if self.added > 0 || self.removed > 0 {
+            write!(f, ", {} added, {} removed", self.added, self.removed)?;
+        }
+        if !self.failed.is_empty() {
+            write!(f, ": {}", self.failed.join(", "))?;
+        }
+        if self.fetched > 0 || !self.fetch_failed.is_empty() {
+            write!(f, "; fetched {} repos", self.fetched)?;
+        }
+        if !self.fetch_failed.is_empty() {
+            write!(f, ", failed to fetch: {}", self.fetch_failed.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub added: usize,
+    pub kept: usize,
+    pub removed: usize,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} kept, {} removed",
+            self.added, self.kept, self.removed
+        )
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
 pub struct Meta {
     pub size: usize,
+    // Bumped on every save, used to notice the store changed on disk since it was loaded
+    #[serde(default)]
+    pub generation: u64,
+    // Format version, see `migrate::VERSION`
+    #[serde(default)]
+    pub version: u32,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+/// User preferences remembered between sessions
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Settings {
+    // Order of the repos in the TUI and `rgm list`
+    pub sort: SortOrder,
+    // What happens on top of the cd when entering a repo
+    pub enter: EnterAction,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
 pub struct Repos {
     pub repos: Vec<Repo>,
     pub meta: Meta,
+    #[serde(default)]
+    pub settings: Settings,
+    // Held from `load` until `save` so other rgm processes can't write in between
+    #[serde(skip)]
+    lock: Option<store::Lock>,
+    // User data of each repo as it was last read or saved, what `save_merged` merges against
+    #[serde(skip)]
+    pub loaded: HashMap<PathBuf, UserData>,
+    // Repos refreshed from git since then, their git state wins in `save_merged`
+    #[serde(skip)]
+    refreshed: HashSet<PathBuf>,
 }
 
 
 impl Repos {
-    pub fn save(&self) -> Result<PathBuf> {
+    pub fn new(repos: Vec<Repo>, meta: Meta) -> Self {
+        Self {
+            repos,
+            meta,
+            settings: Settings::default(),
+            lock: None,
+            loaded: HashMap::new(),
+            refreshed: HashSet::new(),
+        }
+    }
+
+    fn snapshot(&mut self) {
+        self.loaded = self.repos.iter().map(|r| (r.path.clone(), r.user_data())).collect();
+        self.refreshed.clear();
+    }
+
+    /// Save the store, failing if another process saved it since it was loaded.
+    pub fn save(&mut self) -> Result<PathBuf> {
         let file_name = config_file();
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&file_name)
-            .map_err(|err| RgmError {
-                message: err.to_string(),
-            })?;
-        let json = serde_json::to_string(&self).map_err(|err| RgmError {
-            message: err.to_string(),
-        })?;
-        file.write(&json.as_bytes()).map_err(|err| RgmError {
-            message: err.to_string(),
-        })?;
-        return Ok(file_name);
+        // Hold the lock just for this save unless we already have it
+        let _lock = match self.lock {
+            Some(_) => None,
+            None => Some(store::lock(&file_name)?),
+        };
+        let on_disk = if file_name.exists() {
+            read_meta(&file_name)?.generation
+        } else {
+            0
+        };
+        if on_disk != self.meta.generation {
+            return Err(RgmError {
+                message: format!(
+                    "{} was changed by another rgm process since it was loaded",
+                    file_name.display()
+                ),
+            });
+        }
+        self.meta.generation += 1;
+        self.meta.size = self.repos.len();
+        self.meta.version = migrate::VERSION;
+        let res = serde_json::to_string(&self)
+            .map_err(RgmError::from)
+            .and_then(|json| store::write(&file_name, &json));
+        if let Err(e) = res {
+            self.meta.generation -= 1;
+            return Err(e);
+        }
+        self.snapshot();
+        Ok(file_name)
     }
 
-    pub fn load() -> Result<Self> {
-        let mut file = OpenOptions::new()
-            .read(true)
-            .open(config_file())
-            .map_err(|err| RgmError {
-                message: err.to_string(),
-            })?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).map_err(|err| RgmError {
-            message: err.to_string(),
-        })?;
-        let repos: Repos = serde_json::from_str::<Repos>(&contents).map_err(|err| RgmError {
-            message: err.to_string(),
-        })?;
-        Ok(repos)
+    /// Save the store, first merging our tags, aliases, hooks and refreshed repos into the
+    /// version on disk if another process saved it since it was loaded. Returns none if no
+    /// merge was needed, otherwise the changes that were dropped in the merge.
+    pub fn save_merged(&mut self) -> Result<Option<Vec<String>>> {
+        let file_name = config_file();
+        // Hand the lock to `save` too, taking it again there would block on ourselves
+        let had_lock = self.lock.is_some();
+        if !had_lock {
+            self.lock = Some(store::lock(&file_name)?);
+        }
+        let res = self.merge_and_save(&file_name);
+        if !had_lock {
+            self.lock = None;
+        }
+        res
     }
 
-    pub fn update(&mut self){
+    fn merge_and_save(&mut self, file_name: &Path) -> Result<Option<Vec<String>>> {
+        let mut dropped = None;
+        if file_name.exists() && read_meta(file_name)?.generation != self.meta.generation {
+            let (latest, _) = Repos::read(file_name)?;
+            dropped = Some(self.merge(latest.repos));
+            self.meta = latest.meta;
+        }
+        self.save()?;
+        Ok(dropped)
+    }
+
+    /// Take `latest`, the repos another process saved, with our changes merged in. Returns the
+    /// changes that were dropped.
+    ///
+    /// Repos we added or removed since loading are added or removed in `latest` too, and the
+    /// ones we refreshed keep our git state.
+    fn merge(&mut self, latest: Vec<Repo>) -> Vec<String> {
+        let mut dropped = Vec::new();
+        let mut ours: HashMap<PathBuf, Repo> =
+            self.repos.drain(..).map(|r| (r.path.clone(), r)).collect();
+        self.repos = latest
+            .into_iter()
+            .filter(|r| ours.contains_key(&r.path) || !self.loaded.contains_key(&r.path))
+            .collect();
         for repo in self.repos.iter_mut() {
-            repo.update();
+            if let Some(mut our_repo) = ours.remove(&repo.path) {
+                if self.refreshed.contains(&repo.path) {
+                    repo.take_git_state(&mut our_repo);
+                }
+                // Without a snapshot all of ours counts as changed
+                let base = self.loaded.get(&repo.path).cloned().unwrap_or_else(|| repo.user_data());
+                dropped.extend(repo.merge_user_data(our_repo, &base));
+            }
+        }
+        let mut added = Vec::new();
+        for repo in ours.into_values() {
+            match self.loaded.get(&repo.path) {
+                None => added.push(repo),
+                Some(base) if *base != repo.user_data() => dropped.push(format!(
+                    "{}: removed by another rgm process, dropped your changes to it",
+                    repo.name
+                )),
+                Some(_) => {}
+            }
         }
-        if let Err(e) = self.save(){
-            error!("{:?}", e);
+        added.sort_by(|a, b| a.path.cmp(&b.path));
+        self.repos.extend(added);
+        self.meta.size = self.repos.len();
+        dropped
+    }
+
+    /// Load the store, holding its lock until it is dropped or `unlock`ed.
+    ///
+    /// Stores in an older format are upgraded on disk, keeping a copy of the original.
+    pub fn load() -> Result<Self> {
+        let file_name = config_file();
+        let lock = store::lock(&file_name)?;
+        let (mut repos, upgraded) = Repos::read(&file_name)?;
+        repos.lock = Some(lock);
+        if let Some((from, original)) = upgraded {
+            // What was parsed, which is the rolling backup if the store itself was unreadable
+            let backup = store::sibling(&file_name, &format!("v{}", from));
+            fs::write(&backup, original)?;
+            repos.save()?;
+            info!("Upgraded store from version {} to {}", from, migrate::VERSION);
+            eprintln!(
+                "Upgraded {} to format version {}, the original was kept at {}",
+                file_name.display(),
+                migrate::VERSION,
+                backup.display()
+            );
         }
-        info!("Updated repos")
+        Ok(repos)
+    }
+
+    /// Read the store without locking or writing it, for commands that only look at the repos.
+    /// Stores in an older format are upgraded in memory only.
+    pub fn read_only() -> Result<Self> {
+        Ok(Repos::read(&config_file())?.0)
+    }
+
+    /// Release the store lock early, e.g. for long running sessions.
+    pub fn unlock(&mut self) {
+        self.lock = None;
+    }
+
+    /// Read and upgrade the store, if it was upgraded also returns the format version and
+    /// contents it was read as.
+    fn read(file_name: &Path) -> Result<(Self, Option<(u32, String)>)> {
+        store::read(file_name, |contents| {
+            let mut value: serde_json::Value = serde_json::from_str(contents)?;
+            let from = migrate::migrate(&mut value)?;
+            let mut repos = serde_json::from_value::<Repos>(value)?;
+            repos.snapshot();
+            let upgraded = (from < migrate::VERSION).then(|| (from, contents.to_string()));
+            Ok((repos, upgraded))
+        })
+    }
+
+    /// Refresh every repo using up to `jobs` worker threads, then save. With `fetch` the
+    /// remotes are fetched first so ahead/behind is up to date.
+    ///
+    /// The store isn't locked while refreshing, which can take minutes with `fetch`, the
+    /// result is merged into whatever was saved in the meantime.
+    pub fn update<F>(&mut self, jobs: usize, fetch: bool, on_progress: F) -> UpdateSummary
+    where
+        F: FnMut(UpdateProgress),
+    {
+        self.unlock();
+        let summary = self.refresh(|_| true, jobs, fetch, on_progress);
+        self.save_refreshed();
+        info!("Updated repos: {}", summary);
+        summary
+    }
+
+    fn save_refreshed(&mut self) {
+        match self.save_merged() {
+            Ok(dropped) => {
+                for change in dropped.unwrap_or_default() {
+                    warn!("{}", change);
+                }
+            }
+            Err(e) => error!("{:?}", e),
+        }
+    }
+
+    /// Refresh the repos under `path`, picking up newly cloned repos and dropping the ones
+    /// that no longer exist, then save. Like `update` the store isn't locked meanwhile.
+    pub fn update_dir<F>(
+        &mut self,
+        path: &Path,
+        jobs: usize,
+        fetch: bool,
+        on_progress: F,
+    ) -> UpdateSummary
+    where
+        F: FnMut(UpdateProgress),
+    {
+        self.unlock();
+        let summary = self.sync_dir(path, jobs, fetch, on_progress);
+        self.save_refreshed();
+        info!("Updated repos in {}: {}", path.display(), summary);
+        summary
+    }
+
+    /// `update_dir` without saving
+    fn sync_dir<F>(
+        &mut self,
+        path: &Path,
+        jobs: usize,
+        fetch: bool,
+        on_progress: F,
+    ) -> UpdateSummary
+    where
+        F: FnMut(UpdateProgress),
+    {
+        let before = self.repos.len();
+        self.repos.retain(|r| !r.path.starts_with(path) || r.path.exists());
+        let removed = before - self.repos.len();
+
+        let known: HashSet<&Path> = self.repos.iter().map(|r| r.path.as_path()).collect();
+        let new_repos: Vec<Repo> = discover(path)
+            .into_iter()
+            .filter(|r| !known.contains(r.path.as_path()))
+            .collect();
+
+        // New repos were just read from disk, only the known ones need a refresh
+        let mut summary = self.refresh(|r| r.path.starts_with(path), jobs, fetch, on_progress);
+        summary.added = new_repos.len();
+        summary.removed = removed;
+        self.repos.extend(new_repos);
+        self.meta.size = self.repos.len();
+        summary
+    }
+
+    fn refresh<P, F>(
+        &mut self,
+        include: P,
+        jobs: usize,
+        fetch: bool,
+        mut on_progress: F,
+    ) -> UpdateSummary
+    where
+        P: Fn(&Repo) -> bool,
+        F: FnMut(UpdateProgress),
+    {
+        let to_update: Vec<&mut Repo> = self.repos.iter_mut().filter(|r| include(r)).collect();
+        self.refreshed.extend(to_update.iter().map(|r| r.path.clone()));
+        let total = to_update.len();
+        let mut summary = UpdateSummary::default();
+        pool::run(
+            to_update,
+            jobs,
+            |repo: &mut Repo| {
+                // The stored remotes may be out of date, so every repo is fetched
+                let fetch_error = if fetch { repo.fetch().err() } else { None };
+                let before = repo.status.clone();
+                let outcome = match repo.update() {
+                    Ok(true) => UpdateOutcome::Changed(before, repo.status.clone()),
+                    Ok(false) => UpdateOutcome::Unchanged,
+                    Err(e) => UpdateOutcome::Failed(e),
+                };
+                let fetched = fetch && fetch_error.is_none() && !repo.remotes.is_empty();
+                (repo.name.clone(), outcome, fetched, fetch_error)
+            },
+            |(name, outcome, fetched, fetch_error)| {
+                if fetched {
+                    summary.fetched += 1;
+                }
+                if let Some(e) = &fetch_error {
+                    error!("Could not fetch {}: {}", name, e);
+                    summary.fetch_failed.push(name.clone());
+                }
+                match &outcome {
+                    UpdateOutcome::Unchanged => summary.refreshed += 1,
+                    UpdateOutcome::Changed(_, _) => {
+                        summary.refreshed += 1;
+                        summary.changed += 1;
+                    }
+                    UpdateOutcome::Failed(e) => {
+                        error!("Could not update {}: {}", name, e);
+                        summary.failed.push(name.clone());
+                    }
+                }
+                on_progress(UpdateProgress {
+                    done: summary.refreshed + summary.failed.len(),
+                    total,
+                    name: &name,
+                    outcome: &outcome,
+                    fetch_error: fetch_error.as_ref(),
+                });
+            },
+        );
+        summary
     }
 
     pub fn longest_name(&self) -> usize {
@@ -260,82 +1127,399 @@ impl Repos {
         longest
     }
 
-    pub fn from_dir(path: &PathBuf) -> Self {
-        let mut walker = WalkDir::new(path.as_path()).into_iter();
-        let mut repos: Vec<Repo> = Vec::new();
-        loop {
-            let entry = match walker.next() {
-                None => break,
-                Some(Err(_)) => unimplemented!(), //Eventually debug log
-                Some(Ok(entry)) => entry,
-            };
-            let ft = entry.file_type();
-            // Don't care about files
-            if ft.is_file() {
-                continue;
-            } else {
-                // Skip hidden directories
-                if entry
-                    .file_name()
-                    .to_str()
-                    .map(|s| s.starts_with('.'))
-                    .unwrap_or(false)
-                {
-                    walker.skip_current_dir();
-                    continue;
+    /// Merge the repos found under `path` into the store. Repos that are already known keep
+    /// their tags and alias, and with `prune` known repos under `path` that weren't found
+    /// anymore are dropped.
+    pub fn import(&mut self, path: &Path, prune: bool) -> ImportSummary {
+        let found = discover(path);
+        let mut summary = ImportSummary::default();
+        if prune {
+            let found_paths: HashSet<&Path> = found.iter().map(|r| r.path.as_path()).collect();
+            let before = self.repos.len();
+            self.repos
+                .retain(|r| !r.path.starts_with(path) || found_paths.contains(r.path.as_path()));
+            summary.removed = before - self.repos.len();
+        }
+        let known: HashMap<PathBuf, usize> = self
+            .repos
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (r.path.clone(), i))
+            .collect();
+        for repo in found {
+            match known.get(&repo.path) {
+                Some(&i) => {
+                    let old = std::mem::replace(&mut self.repos[i], repo);
+                    self.repos[i].inherit(old);
+                    summary.kept += 1;
                 }
-                let g_dir = entry.path().join(".git");
-                // Found a git subdirectory, no need to recurse in this dir anymore.
-                if g_dir.exists() && g_dir.is_dir() {
-                    walker.skip_current_dir();
-                    let raw = Repository::open(entry.path());
-                    let repo = Repo::from_raw(raw.unwrap());
-                    match repo {
-                        Ok(v) => repos.push(v),
-                        Err(s) => println!(
-                            "Couldn't get repo info at path: {} err: {:?}",
-                            entry.path().display(),
-                            s
-                        ),
-                    }
+                None => {
+                    self.repos.push(repo);
+                    summary.added += 1;
                 }
             }
         }
-        Repos {
-            meta: Meta { size: repos.len() },
-            repos,
+        self.meta.size = self.repos.len();
+        info!("Imported {}: {}", path.display(), summary);
+        summary
+    }
+}
+
+fn read_meta(file_name: &Path) -> Result<Meta> {
+    #[derive(Deserialize)]
+    struct MetaOnly {
+        meta: Meta,
+    }
+    store::read(file_name, |contents| {
+        Ok(serde_json::from_str::<MetaOnly>(contents)?.meta)
+    })
+}
+
+/// Walk `path` and collect every (non hidden) git repo below it.
+fn discover(path: &Path) -> Vec<Repo> {
+    let mut walker = WalkDir::new(path).into_iter();
+    let mut repos: Vec<Repo> = Vec::new();
+    loop {
+        let entry = match walker.next() {
+            None => break,
+            Some(Err(e)) => {
+                debug!("Skipping unreadable entry: {}", e);
+                continue;
+            }
+            Some(Ok(entry)) => entry,
+        };
+        let ft = entry.file_type();
+        // Don't care about files
+        if ft.is_file() {
+            continue;
+        } else {
+            // Skip hidden directories
+            if entry
+                .file_name()
+                .to_str()
+                .map(|s| s.starts_with('.'))
+                .unwrap_or(false)
+            {
+                walker.skip_current_dir();
+                continue;
+            }
+            let g_dir = entry.path().join(".git");
+            // Found a git subdirectory, no need to recurse in this dir anymore.
+            if g_dir.exists() && g_dir.is_dir() {
+                walker.skip_current_dir();
+                let repo = Repository::open(entry.path()).and_then(Repo::from_raw);
+                match repo {
+                    Ok(v) => repos.push(v),
+                    Err(s) => println!(
+                        "Couldn't get repo info at path: {} err: {:?}",
+                        entry.path().display(),
+                        s
+                    ),
+                }
+            }
         }
     }
+    repos
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::query::Query;
+    use crate::testutil::{commit, commit_file, init_repo, repo, test_dir};
 
-    fn empty_repo() -> Repo {
-        Repo::new(
-            PathBuf::from("/tmp/test"),
-            String::from("test"),
-            String::from("main"),
-            Some(Status::Clean),
-            vec![],
-            vec![],
-            vec![],
-        )
-    }
-    
     #[test]
     fn test_repo_alias(){
-        let repo = empty_repo();
+        let mut repo = repo("test", "/tmp/test", &[]);
         repo.add_alias(String::from("alias"));
-        assert_eq!(&repo.alias, String::from("alias"))
+        assert_eq!(repo.alias, Some(String::from("alias")))
+    }
+
+    #[test]
+    fn test_status_tracking(){
+        let root = test_dir("status");
+        let raw = init_repo(&root);
+        let tracking = |raw: &Repository| get_status(raw).0.unwrap().tracking;
+        assert_eq!(tracking(&raw), Tracking::NoUpstream);
+
+        // Upstream on a remote other than origin, with a different branch name
+        let base = raw.head().unwrap().peel_to_commit().unwrap().id();
+        raw.remote("upstream", "/nonexistent").unwrap();
+        raw.reference("refs/remotes/upstream/trunk", base, false, "").unwrap();
+        let head = raw.head().unwrap();
+        let mut branch = git2::Branch::wrap(head);
+        branch.set_upstream(Some("upstream/trunk")).unwrap();
+        let status = get_status(&raw).0.unwrap();
+        assert_eq!(status.tracking, Tracking::Even);
+        assert_eq!(status.upstream.as_deref(), Some("upstream/trunk"));
+
+        commit(&raw, "one");
+        assert_eq!(tracking(&raw), Tracking::Ahead(1));
+        raw.reference("refs/remotes/upstream/trunk", commit(&raw, "two"), true, "").unwrap();
+        raw.reset(&raw.find_object(base, None).unwrap(), git2::ResetType::Soft, None).unwrap();
+        assert_eq!(tracking(&raw), Tracking::Behind(2));
+        commit(&raw, "local");
+        assert_eq!(tracking(&raw), Tracking::Diverged(1, 2));
+
+        // Dirty and diverged at the same time
+        std::fs::write(root.join("new.txt"), "new").unwrap();
+        let status = get_status(&raw).0.unwrap();
+        assert_eq!(status.changes.untracked, 1);
+        assert_eq!(status.tracking, Tracking::Diverged(1, 2));
+
+        raw.find_reference("refs/remotes/upstream/trunk").unwrap().delete().unwrap();
+        assert_eq!(tracking(&raw), Tracking::UpstreamGone);
+        raw.set_head_detached(base).unwrap();
+        assert_eq!(tracking(&raw), Tracking::Detached);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_status_operation(){
+        let root = test_dir("operation");
+        let raw = Repository::init(&root).unwrap();
+        assert_eq!(get_status(&raw).0.unwrap().tracking, Tracking::Unborn);
+        // The branch exists before its first commit
+        assert!(branch_name(&raw).is_some());
+
+        init_repo(&root);
+        let head = raw.head().unwrap().target().unwrap();
+        std::fs::write(raw.path().join("MERGE_HEAD"), format!("{}\n", head)).unwrap();
+        let status = get_status(&raw).0.unwrap();
+        assert_eq!(status.operation, Some(Operation::Merging));
+        assert!(status.labels().contains(&"merging"));
+        std::fs::remove_file(raw.path().join("MERGE_HEAD")).unwrap();
+        std::fs::write(raw.path().join("BISECT_LOG"), "").unwrap();
+        assert_eq!(get_status(&raw).0.unwrap().operation, Some(Operation::Bisecting));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_read_work(){
+        let root = test_dir("work");
+        let mut raw = init_repo(&root.join("main"));
+        let mut repo = Repo::from_raw(Repository::open(root.join("main")).unwrap()).unwrap();
+        assert!(repo.has_labels().is_empty());
+
+        std::fs::write(root.join("main/file.txt"), "wip").unwrap();
+        let sig = git2::Signature::now("rgm", "rgm@example.com").unwrap();
+        raw.stash_save(&sig, "wip", Some(git2::StashFlags::INCLUDE_UNTRACKED)).unwrap();
+        raw.worktree("feature", &root.join("feature"), None).unwrap();
+        repo.update().unwrap();
+        assert_eq!(repo.stashes, 1);
+        assert_eq!(repo.worktrees, vec![root.join("feature")]);
+        assert_eq!(repo.has_labels(), vec!["stashes", "worktrees"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_submodules(){
+        let root = test_dir("submodules");
+        init_repo(&root.join("lib"));
+        let raw = init_repo(&root.join("main"));
+        let url = root.join("lib");
+        let mut submodule = raw.submodule(url.to_str().unwrap(), Path::new("lib"), true).unwrap();
+        submodule.clone(None).unwrap();
+        submodule.add_finalize().unwrap();
+        commit_file(&raw, "readme");
+        let mut repo = Repo::from_raw(Repository::open(root.join("main")).unwrap()).unwrap();
+        assert_eq!(repo.submodules, vec![Submodule { path: PathBuf::from("lib"), dirty: Some(false) }]);
+        assert_eq!(repo.has_labels(), vec!["submodules"]);
+
+        // Checked out at another commit than recorded
+        commit(&Repository::open(root.join("main/lib")).unwrap(), "moved");
+        repo.update().unwrap();
+        assert_eq!(repo.submodules[0].dirty, Some(true));
+        assert_eq!(repo.has_labels(), vec!["submodules", "dirty-submodules"]);
+        assert!(Query::parse("has:dirty-submodules").unwrap().matches(&repo));
+
+        // Not utf-8, so git2 can't look up its status
+        let gitmodules = b"[submodule \"lib\xff\"]\n\tpath = lib\n\turl = ../lib\n";
+        std::fs::write(root.join("main/.gitmodules"), gitmodules).unwrap();
+        repo.update().unwrap();
+        assert_eq!(repo.submodules[0].dirty, None);
+        assert_eq!(repo.has_labels(), vec!["submodules"]);
+        assert!(!Query::parse("has:dirty-submodules").unwrap().matches(&repo));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_fetch_staleness(){
+        let root = test_dir("fetch");
+        let origin = init_repo(&root.join("origin"));
+        commit(&origin, "init");
+        Repository::clone(root.join("origin").to_str().unwrap(), root.join("clone")).unwrap();
+        let mut repo = Repo::from_raw(Repository::open(root.join("clone")).unwrap()).unwrap();
+        // Cloning counts as a fetch
+        assert!(repo.last_fetch.is_some() && !repo.is_stale());
+        repo.last_fetch = None;
+        assert!(repo.is_stale());
+
+        commit(&origin, "new");
+        repo.fetch().unwrap();
+        repo.update().unwrap();
+        assert!(repo.last_fetch.is_some() && !repo.is_stale());
+        assert_eq!(repo.status.as_ref().unwrap().tracking, Tracking::Behind(1));
+        repo.last_fetch = Some(utils::now() - STALE_AFTER - 1);
+        assert!(repo.is_stale());
+
+        // Without remotes there is nothing to be stale against
+        let local = Repo::from_raw(Repository::open(root.join("origin")).unwrap()).unwrap();
+        assert!(!local.is_stale());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_import_merge(){
+        let root = test_dir("import");
+        init_repo(&root.join("one"));
+        init_repo(&root.join("two"));
+        let mut repos = Repos::default();
+        let summary = repos.import(&root, false);
+        assert_eq!((summary.added, summary.kept, summary.removed), (2, 0, 0));
+
+        for r in repos.repos.iter_mut() {
+            r.add_tags(&[String::from("keep")]);
+        }
+        std::fs::remove_dir_all(root.join("two")).unwrap();
+        init_repo(&root.join("three"));
+        let summary = repos.import(&root, true);
+        assert_eq!((summary.added, summary.kept, summary.removed), (1, 1, 1));
+        assert_eq!(repos.meta.size, 2);
+        let one = repos.repos.iter().find(|r| r.name == "one").unwrap();
+        assert_eq!(one.tags, vec![String::from("keep")]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_update_dir(){
+        let root = test_dir("update-dir");
+        let under = root.join("under");
+        init_repo(&under.join("kept"));
+        init_repo(&under.join("gone"));
+        init_repo(&root.join("outside"));
+        let mut repos = Repos::default();
+        repos.import(&root, false);
+        let outside_status = repos.repos.iter().find(|r| r.name == "outside").unwrap().status.clone();
+
+        std::fs::write(under.join("kept/wip.txt"), "wip").unwrap();
+        std::fs::remove_dir_all(under.join("gone")).unwrap();
+        init_repo(&under.join("cloned"));
+        // Changed too, but not under the updated dir
+        std::fs::write(root.join("outside/wip.txt"), "wip").unwrap();
+
+        let summary = repos.sync_dir(&under, 1, false, |_| {});
+        assert_eq!((summary.added, summary.removed), (1, 1));
+        let mut names: Vec<&str> = repos.repos.iter().map(|r| r.name.as_str()).collect();
+        names.sort_unstable();
+        assert_eq!(names, vec!["cloned", "kept", "outside"]);
+        assert_eq!(repos.meta.size, 3);
+        let kept = repos.repos.iter().find(|r| r.name == "kept").unwrap();
+        assert_eq!(kept.status.as_ref().unwrap().changes.untracked, 1);
+        let outside = repos.repos.iter().find(|r| r.name == "outside").unwrap();
+        assert_eq!(outside.status, outside_status);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_merge_user_data(){
+        let mut merged = repo("test", "/tmp/test", &["a"]);
+        let mut other = repo("test", "/tmp/test", &["a", "b"]);
+        other.add_alias(String::from("alias"));
+        let base = merged.user_data();
+        assert!(merged.merge_user_data(other, &base).is_empty());
+        assert_eq!(merged.alias, Some(String::from("alias")));
+        assert_eq!(merged.tags, vec![String::from("a"), String::from("b")]);
+    }
+
+    #[test]
+    fn test_merge_same_alias(){
+        let base = Repo { alias: Some(String::from("old")), ..repo("test", "/tmp/test", &[]) };
+        let base = base.user_data();
+        let mut theirs = Repo { alias: Some(String::from("disk")), ..repo("test", "/tmp/test", &[]) };
+        let ours = Repo { alias: Some(String::from("tui")), ..repo("test", "/tmp/test", &[]) };
+        let dropped = theirs.merge_user_data(ours, &base);
+        assert_eq!(theirs.alias, Some(String::from("tui")));
+        assert_eq!(dropped.len(), 1);
+        assert!(dropped[0].contains("'disk'"));
+
+        // Left alone by us, so theirs is kept
+        let mut theirs = Repo { alias: Some(String::from("disk")), ..repo("test", "/tmp/test", &[]) };
+        let ours = Repo { alias: Some(String::from("old")), ..repo("test", "/tmp/test", &[]) };
+        assert!(theirs.merge_user_data(ours, &base).is_empty());
+        assert_eq!(theirs.alias, Some(String::from("disk")));
+    }
+
+    #[test]
+    fn test_merge_tags(){
+        let base = repo("test", "/tmp/test", &["a", "b"]).user_data();
+        let mut theirs = repo("test", "/tmp/test", &["a", "b", "c"]);
+        let ours = repo("test", "/tmp/test", &["b", "d"]);
+        theirs.merge_user_data(ours, &base);
+        assert_eq!(theirs.tags, vec!["b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_merge_hook(){
+        let hooked = |hook: Option<&str>| Repo {
+            on_enter: hook.map(String::from),
+            ..repo("test", "/tmp/test", &[])
+        };
+        // Set in the TUI while the store already had one
+        let base = hooked(Some("disk")).user_data();
+        let mut theirs = hooked(Some("disk"));
+        assert!(theirs.merge_user_data(hooked(Some("tui")), &base).is_empty());
+        assert_eq!(theirs.on_enter.as_deref(), Some("tui"));
+
+        // Cleared in the TUI
+        let mut theirs = hooked(Some("disk"));
+        assert!(theirs.merge_user_data(hooked(None), &base).is_empty());
+        assert_eq!(theirs.on_enter, None);
+    }
+
+    #[test]
+    fn test_merge_removed_repo(){
+        let mut repos = Repos::new(vec![repo("kept", "/tmp/kept", &[]), repo("gone", "/tmp/gone", &[])], Meta::default());
+        repos.snapshot();
+        repos.repos[1].add_alias(String::from("g"));
+        let dropped = repos.merge(vec![repo("kept", "/tmp/kept", &[])]);
+        assert_eq!(repos.repos.len(), 1);
+        assert_eq!(dropped.len(), 1);
+        assert!(dropped[0].starts_with("gone:"));
+    }
+
+    #[test]
+    fn test_merge_refreshed(){
+        let mut repos = Repos::new(
+            vec![repo("api", "/tmp/api", &[]), repo("gone", "/tmp/gone", &[])],
+            Meta::default(),
+        );
+        repos.snapshot();
+        // What an unlocked update does meanwhile
+        repos.repos[0].branch = String::from("feature");
+        repos.refreshed.insert(PathBuf::from("/tmp/api"));
+        repos.repos.remove(1);
+        repos.repos.push(repo("cloned", "/tmp/cloned", &[]));
+
+        // While another process tagged api and added a repo
+        let latest = vec![
+            repo("api", "/tmp/api", &["backend"]),
+            repo("gone", "/tmp/gone", &[]),
+            repo("web", "/tmp/web", &[]),
+        ];
+        assert!(repos.merge(latest).is_empty());
+        let names: Vec<&str> = repos.repos.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["api", "web", "cloned"]);
+        assert_eq!(repos.repos[0].branch, "feature");
+        assert_eq!(repos.repos[0].tags, vec!["backend"]);
+        assert_eq!(repos.meta.size, 3);
     }
 
     #[test]
     fn test_repo_tags(){
-        let repo = empty_repo();
-        let mut tags = vec![String::from("tag1"), String::from("tag2")];
-        repo.add_tags(&mut tags);
+        let mut repo = repo("test", "/tmp/test", &[]);
+        let tags = vec![String::from("tag1"), String::from("tag2")];
+        repo.add_tags(&tags);
         
         assert_eq!(repo.tags.len(), 2);
         assert!(repo.tags.contains(&String::from("tag1")));
@@ -1,7 +1,8 @@
-use crate::repo::{Repo, Status};
+use crate::repo::{Operation, Repo, Status, Tracking};
+use crate::utils;
 use tui::{
     style::{Color, Modifier, Style},
-    text::{Span, Spans, Text},
+    text::{Span, Spans},
 };
 const COLLAPSED: &str = "▶ ";
 const EXPANDED: &str = "▼ ";
@@ -11,8 +12,8 @@ pub struct RepoItem<'a> {
     longest_name: usize,
     indent: u8,
     expanded: bool,
-    selected: bool,
-    status_start: u8,
+    // Char indices of the name to highlight (e.g. fuzzy matches)
+    highlight: &'a [usize],
 }
 
 impl<'a> RepoItem<'a> {
@@ -21,38 +22,76 @@ impl<'a> RepoItem<'a> {
         longest_name: usize,
         indent: u8,
         expanded: bool,
-        selected: bool,
-        status_start: u8,
+        highlight: &'a [usize],
     ) -> Self {
         Self {
             repo,
             longest_name,
             indent,
             expanded,
-            selected,
-            status_start,
+            highlight,
         }
     }
 
+    /// Name split into spans, with the highlighted chars styled
+    fn name_spans(&self) -> Vec<Span<'a>> {
+        let style = Style::default()
+            .fg(Color::LightYellow)
+            .add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
+        let mut spans = Vec::new();
+        let mut current = String::new();
+        let mut current_highlighted = false;
+        for (i, c) in self.repo.name.chars().enumerate() {
+            let highlighted = self.highlight.contains(&i);
+            if highlighted != current_highlighted && !current.is_empty() {
+                spans.push(if current_highlighted {
+                    Span::styled(std::mem::take(&mut current), style)
+                } else {
+                    Span::raw(std::mem::take(&mut current))
+                });
+            }
+            current_highlighted = highlighted;
+            current.push(c);
+        }
+        if !current.is_empty() {
+            spans.push(if current_highlighted {
+                Span::styled(current, style)
+            } else {
+                Span::raw(current)
+            });
+        }
+        spans
+    }
+
     pub fn text(self) -> Vec<Spans<'a>> {
-        let prefix = if self.expanded {
-            String::from(EXPANDED)
-        } else {
-            String::from(COLLAPSED)
-        };
-        let name = self.repo.name.clone();
-        let num_spaces = (self.longest_name - name.len()) + 3;
+        let prefix = marker(self.indent, self.expanded);
+        // Names line up across indent levels, `longest_name` includes the indent
+        let num_spaces = self
+            .longest_name
+            .saturating_sub(self.repo.name.len() + 2 * self.indent as usize)
+            + 3;
         let spaces = (0..num_spaces).map(|_| " ").collect::<String>();
-        let status = self.repo.status.as_ref().unwrap_or(&Status::Other);
         let mut spans = Vec::<Spans>::new();
-        let first_line = Spans::from(vec![
-            Span::raw(prefix),
-            Span::raw(name),
+        let status_color = self
+            .repo
+            .status
+            .as_ref()
+            .map(get_color_for_status)
+            .unwrap_or(Color::White);
+        let mut first_line = vec![Span::styled(prefix, Style::default().fg(status_color))];
+        first_line.extend(self.name_spans());
+        first_line.extend(vec![
             Span::raw(spaces),
             Span::raw(" | "),
             Span::raw(self.repo.tags.join(", ")),
         ]);
-        spans.push(first_line);
+        if self.repo.is_stale() {
+            first_line.push(Span::styled(
+                format!("  ({})", fetch_age(self.repo.last_fetch)),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        spans.push(Spans::from(first_line));
         if self.expanded {
             spans.push(Spans::from(format!("    Branch: {}\r\n", self.repo.branch)));
             spans.push(Spans::from(format!(
@@ -60,20 +99,209 @@ impl<'a> RepoItem<'a> {
                 self.repo.remotes
             )));
             spans.push(Spans::from(format!("    Alias: {:?}\r\n", self.repo.alias)));
-            spans.push(Spans::from(format!("    Status: {}\r\n", status.display())));
+            let status = match &self.repo.status {
+                Some(status) => status.to_string(),
+                None => String::from("Unknown"),
+            };
+            spans.push(Spans::from(vec![
+                Span::raw("    Status: "),
+                Span::styled(status, Style::default().fg(status_color)),
+            ]));
+            if let Some(commit) = &self.repo.last_commit {
+                spans.push(Spans::from(format!(
+                    "    Last commit: {} ({}, {})",
+                    commit.summary,
+                    commit.author,
+                    utils::format_age(commit.time)
+                )));
+            }
+            if !self.repo.remotes.is_empty() {
+                let style = if self.repo.is_stale() {
+                    Style::default().fg(Color::DarkGray)
+                } else {
+                    Style::default()
+                };
+                let age = match self.repo.last_fetch {
+                    Some(time) => utils::format_age(time),
+                    None => String::from("never"),
+                };
+                spans.push(Spans::from(vec![
+                    Span::raw("    Last fetch: "),
+                    Span::styled(age, style),
+                ]));
+            }
+            if let Some(modified) = self.repo.worktree_modified {
+                spans.push(Spans::from(format!(
+                    "    Modified: {}",
+                    utils::format_age(modified)
+                )));
+            }
+            if self.repo.stashes > 0 {
+                spans.push(Spans::from(format!("    Stashes: {}", self.repo.stashes)));
+            }
+            if !self.repo.submodules.is_empty() {
+                let mut line = vec![Span::raw("    Submodules: ")];
+                for (i, submodule) in self.repo.submodules.iter().enumerate() {
+                    if i > 0 {
+                        line.push(Span::raw(", "));
+                    }
+                    let path = submodule.path.display().to_string();
+                    line.push(match submodule.dirty {
+                        Some(true) => Span::styled(
+                            format!("{} (dirty)", path),
+                            Style::default().fg(Color::Yellow),
+                        ),
+                        Some(false) => Span::raw(path),
+                        None => Span::styled(format!("{} (unknown)", path), Style::default().fg(Color::Gray)),
+                    });
+                }
+                spans.push(Spans::from(line));
+            }
+            for worktree in self.repo.worktrees.iter() {
+                spans.push(Spans::from(format!("    Worktree: {}", worktree.display())));
+            }
         }
         spans
     }
 }
 
+fn marker(indent: u8, expanded: bool) -> String {
+    let indent = "  ".repeat(indent as usize);
+    if expanded {
+        indent + EXPANDED
+    } else {
+        indent + COLLAPSED
+    }
+}
+
+/// Directory in the tree view, with how many of the repos under it need attention
+pub struct DirItem<'a> {
+    label: &'a str,
+    indent: u8,
+    expanded: bool,
+    repos: Vec<&'a Repo>,
+}
+
+impl<'a> DirItem<'a> {
+    pub fn new(label: &'a str, indent: u8, expanded: bool, repos: Vec<&'a Repo>) -> Self {
+        Self {
+            label,
+            indent,
+            expanded,
+            repos,
+        }
+    }
+
+    pub fn text(self) -> Vec<Spans<'a>> {
+        let mut line = vec![
+            Span::raw(marker(self.indent, self.expanded)),
+            Span::styled(
+                format!("{}/", self.label.trim_end_matches('/')),
+                Style::default().fg(Color::Blue).add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(format!("  {} repos", self.repos.len())),
+        ];
+        let counts = attention_counts(&self.repos);
+        for ((text, color), n) in ATTENTION.iter().zip(counts) {
+            if n > 0 {
+                line.push(Span::raw(", "));
+                line.push(Span::styled(format!("{} {}", n, text), Style::default().fg(*color)));
+            }
+        }
+        vec![Spans::from(line)]
+    }
+}
+
+/// What the repos under a directory are counted by, see `attention_counts`
+const ATTENTION: [(&str, Color); 5] = [
+    ("dirty", Color::Yellow),
+    ("ahead", Color::Cyan),
+    ("behind", Color::Cyan),
+    ("diverged", Color::Red),
+    ("in progress", Color::Magenta),
+];
+
+/// Number of `repos` in each of `ATTENTION`. A diverged repo only counts as diverged, not as
+/// ahead and behind too.
+fn attention_counts(repos: &[&Repo]) -> [usize; 5] {
+    let mut counts = [0; 5];
+    for status in repos.iter().filter_map(|r| r.status.as_ref()) {
+        if status.tracking == Tracking::Bare {
+            continue;
+        }
+        let hits = [
+            !status.changes.is_clean(),
+            matches!(status.tracking, Tracking::Ahead(_)),
+            matches!(status.tracking, Tracking::Behind(_)),
+            matches!(status.tracking, Tracking::Diverged(_, _)),
+            status.operation.is_some(),
+        ];
+        for (count, hit) in counts.iter_mut().zip(hits) {
+            if hit {
+                *count += 1;
+            }
+        }
+    }
+    counts
+}
+
+fn fetch_age(last_fetch: Option<u64>) -> String {
+    match last_fetch {
+        Some(time) => format!("fetched {}", utils::format_age(time)),
+        None => String::from("never fetched"),
+    }
+}
+
 pub fn get_color_for_status(status: &Status) -> Color {
-    match status {
-        Status::Bare => Color::White,
-        // Todo
-        Status::Diverged(_, _) => Color::Red,
-        Status::Clean => Color::Green,
-        Status::Dirty => Color::Yellow,
-        Status::Detached => Color::Red,
-        Status::Other => Color::White,
+    match status.operation {
+        Some(Operation::Merging) => return Color::Magenta,
+        Some(Operation::Rebasing) | Some(Operation::ApplyingPatches) => return Color::LightMagenta,
+        Some(Operation::CherryPicking) | Some(Operation::Reverting) => return Color::LightRed,
+        Some(Operation::Bisecting) => return Color::LightBlue,
+        None => {}
+    }
+    if status.changes.conflicted > 0 {
+        return Color::Red;
+    }
+    match status.tracking {
+        Tracking::Bare => Color::White,
+        Tracking::Unborn => Color::DarkGray,
+        Tracking::Detached => Color::LightRed,
+        Tracking::Diverged(_, _) | Tracking::UpstreamGone => Color::Red,
+        _ if !status.changes.is_clean() => Color::Yellow,
+        Tracking::Ahead(_) | Tracking::Behind(_) => Color::Cyan,
+        Tracking::Even => Color::Green,
+        Tracking::NoUpstream => Color::White,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::repo::Changes;
+    use crate::testutil::repo;
+
+    fn tracking(name: &str, tracking: Tracking) -> Repo {
+        Repo {
+            status: Some(Status {
+                changes: Changes::default(),
+                tracking,
+                upstream: None,
+                operation: None,
+            }),
+            ..repo(name, format!("/src/{}", name), &[])
+        }
+    }
+
+    #[test]
+    fn test_attention_counts() {
+        let diverged = tracking("diverged", Tracking::Diverged(1, 2));
+        assert_eq!(attention_counts(&[&diverged]), [0, 0, 0, 1, 0]);
+
+        let ahead = tracking("ahead", Tracking::Ahead(1));
+        let mut dirty = tracking("dirty", Tracking::Behind(3));
+        dirty.status.as_mut().unwrap().changes.modified = 2;
+        let unknown = repo("unknown", "/src/unknown", &[]);
+        assert_eq!(attention_counts(&[&diverged, &ahead, &dirty, &unknown]), [1, 1, 1, 1, 0]);
     }
 }
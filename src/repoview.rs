@@ -1,9 +1,17 @@
+use crate::batch::Batch;
+use crate::filter::Filters;
+use crate::gitops::GitOp;
+use crate::shell::EnterAction;
 use crate::input::Input;
-use crate::repo::{QueryOpts, Repo, Repos, Meta};
-use crate::repoitem::RepoItem;
+use crate::query::{best_match, Query};
+use crate::repo::{Meta, Repo, Repos, Settings, UserData};
+use crate::repoitem::{DirItem, RepoItem};
 use crate::screen::Draw;
+use crate::sorting::{self, SortOrder, TreeSorter};
 use crate::utils;
-use log::{debug, info};
+use log::{error, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use tui::{
     backend::Backend,
     layout::Rect,
@@ -18,147 +26,770 @@ pub struct RepoView {
     column_widths: Vec<usize>,
     pub select_mode: bool,
     selected: Vec<usize>,
-    filter: Vec<usize>,
+    filters: Filters,
+    search: Option<Search>,
+    // Cursor row and search from before the search being typed, restored if it is cancelled
+    search_preview: Option<(Option<usize>, Option<Search>)>,
+    // Indices into items of the repos that pass the filters, in ranked order
+    visible: Vec<usize>,
+    // Lines of the list, the cursor and search work on these
+    rows: Vec<Row>,
+    // Show the visible repos under their directories
+    tree: bool,
+    // Directories folded away in the tree
+    collapsed: HashSet<PathBuf>,
+    // Char indices of fuzzy matches in the names of visible items
+    highlights: HashMap<usize, Vec<usize>>,
+    meta: Meta,
+    settings: Settings,
+    // Snapshot of the store when loaded, to merge against on save
+    loaded: HashMap<PathBuf, UserData>,
+    // Git operation running (or finished but not dismissed) on a batch of repos
+    batch: Option<Batch>,
+}
+
+/// Line of the list, a repo or a directory above some repos in tree mode
+enum Row {
+    Repo {
+        item: usize,
+        depth: u8,
+    },
+    Dir {
+        path: PathBuf,
+        label: String,
+        depth: u8,
+        // All visible repos under the directory
+        items: Vec<usize>,
+    },
+}
+
+/// Rows for `entries` (path components and item index, in tree order) which all share the first
+/// `level` components, the path of `base`.
+///
+/// Directories with a single subdirectory and no repo of their own are merged into one row,
+/// e.g. `work/go`.
+fn push_tree_rows(
+    entries: &[(Vec<&str>, usize)],
+    level: usize,
+    base: &Path,
+    depth: u8,
+    collapsed: &HashSet<PathBuf>,
+    rows: &mut Vec<Row>,
+) {
+    let mut start = 0;
+    while start < entries.len() {
+        let (components, item) = &entries[start];
+        if components.len() == level {
+            rows.push(Row::Repo { item: *item, depth });
+            start += 1;
+            continue;
+        }
+        let name = components[level];
+        let end = start
+            + entries[start..]
+                .iter()
+                .take_while(|(c, _)| c.get(level) == Some(&name))
+                .count();
+        let group = &entries[start..end];
+        start = end;
+
+        if group[0].0.len() == level + 1 {
+            // A repo, and any repos nested in it
+            rows.push(Row::Repo { item: group[0].1, depth });
+            push_tree_rows(&group[1..], level + 1, &base.join(name), depth + 1, collapsed, rows);
+            continue;
+        }
+        let mut dir_level = level + 1;
+        let first = &group[0].0;
+        while group
+            .iter()
+            .all(|(c, _)| c.len() > dir_level + 1 && c[dir_level] == first[dir_level])
+        {
+            dir_level += 1;
+        }
+        let path = base.join(first[level..dir_level].join("/"));
+        let label = if depth == 0 {
+            path.display().to_string()
+        } else {
+            first[level..dir_level].join("/")
+        };
+        let expanded = !collapsed.contains(&path);
+        rows.push(Row::Dir {
+            path: path.clone(),
+            label,
+            depth,
+            items: group.iter().map(|(_, i)| *i).collect(),
+        });
+        if expanded {
+            push_tree_rows(group, dir_level, &path, depth + 1, collapsed, rows);
+        }
+    }
+}
+
+/// Search over the visible rows, moves the cursor between matches without hiding anything
+struct Search {
+    text: String,
+    query: Query,
+    // Visible rows that match, in display order
+    rows: Vec<usize>,
+}
+
+/// Ranking boost for repos entered recently, so the ones in use float to the top
+fn recency_boost(repo: &Repo) -> i64 {
+    const HOUR: u64 = 60 * 60;
+    const DAY: u64 = 24 * HOUR;
+    let age = match repo.last_entered {
+        Some(t) => utils::now().saturating_sub(t),
+        None => return 0,
+    };
+    match age {
+        a if a < HOUR => 40,
+        a if a < DAY => 30,
+        a if a < 7 * DAY => 20,
+        a if a < 30 * DAY => 10,
+        _ => 0,
+    }
 }
 
 impl RepoView {
     pub fn new(repos: Repos) -> Self {
         let longest = repos.longest_name();
-        Self {
+        let mut view = Self {
             items: StatefulList::new(repos.repos),
             expanded: Vec::<usize>::new(),
             column_widths: vec![longest],
             select_mode: false,
             selected: Vec::<usize>::new(),
-            filter: Vec::<usize>::new(),
-        }
+            filters: Filters::default(),
+            search: None,
+            search_preview: None,
+            visible: vec![],
+            rows: vec![],
+            tree: false,
+            collapsed: HashSet::new(),
+            highlights: HashMap::new(),
+            meta: repos.meta,
+            settings: repos.settings,
+            loaded: repos.loaded,
+            batch: None,
+        };
+        view.refilter();
+        view
     }
 
     pub fn save_repos(self){
-        let repos = Repos {
-            meta: Meta { size: self.items.items.len() },
-            repos: self.items.items
-        };
-        repos.save();
+        let mut repos = Repos::new(self.items.items, self.meta);
+        repos.settings = self.settings;
+        repos.loaded = self.loaded;
+        match repos.save_merged() {
+            Ok(Some(dropped)) => {
+                warn!("Store changed on disk while open, merged changes");
+                eprintln!("Repos were changed by another rgm process, merged your changes into them");
+                for change in dropped {
+                    warn!("{}", change);
+                    eprintln!("  {}", change);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("{:?}", e);
+                eprintln!("Could not save repos: {}", e);
+            }
+        }
+    }
+
+    fn curr_row(&self) -> Option<&Row> {
+        self.items.selected().and_then(|row| self.rows.get(row))
+    }
+
+    /// Index into items of the repo under the cursor, none on a directory
+    fn curr_index(&self) -> Option<usize> {
+        match self.curr_row() {
+            Some(Row::Repo { item, .. }) => Some(*item),
+            _ => None,
+        }
     }
 
     pub fn curr(&self) -> Option<&Repo> {
-        if let Some(s) = self.items.selected(){
-            Some(&self.items.items[s])
-        } else { None }
+        self.curr_index().map(|i| &self.items.items[i])
+    }
+
+    /// Mark the repo under the cursor as entered
+    pub fn enter(&mut self) {
+        if let Some(i) = self.curr_index() {
+            self.items.items[i].enter();
+        }
     }
 
     pub fn reset_selected(&mut self) {
         self.selected = Vec::<usize>::new();
     }
 
+    /// Item indices commands apply to: the selected repos, or the ones under the cursor
+    fn targets(&self) -> Vec<usize> {
+        if self.selected.is_empty() {
+            self.targets_under_cursor()
+        } else {
+            self.selected.clone()
+        }
+    }
+
     pub fn tag_command(&mut self, cmd: &[&str]) -> Option<Input> {
         // Convert to Vec<String>
-        let mut tags = cmd.iter().map(|v| String::from(*v)).collect();
-        info!("Adding tags {:?} to {:?} repos", tags, self.selected.len());
-        for idx in &self.selected[..] {
-            self.items.items[*idx].add_tags(&mut tags);
+        let tags: Vec<String> = cmd.iter().map(|v| String::from(*v)).collect();
+        let targets = self.targets();
+        info!("Adding tags {:?} to {:?} repos", tags, targets.len());
+        for idx in targets {
+            self.items.items[idx].add_tags(&tags);
         }
         None
     }
 
     pub fn alias_command(&mut self, cmd: &[&str]) -> Option<Input> {
         let alias = cmd.join("-");
-        if self.selected.len() > 1 {
-            return Some(Input::warning(String::from(
-                "Not applying alias to multiple selected repos",
-            )));
-        } else {
-            self.items.items[self.selected[0]].add_alias(alias);
+        match self.targets()[..] {
+            [idx] => self.items.items[idx].add_alias(alias),
+            [] => return Some(Input::warning(String::from("No repo to alias"))),
+            _ => {
+                return Some(Input::warning(String::from(
+                    "Not applying alias to multiple selected repos",
+                )))
+            }
         }
         None
     }
 
-    pub fn filter_command(&mut self, cmd: &[&str]) -> Option<Input> {
-        let search_str = cmd.join("-");
-        for (i, repo) in self.items.items.iter().enumerate() {
-            let filter = !repo.query(&search_str, QueryOpts::Name);
-            if filter {
-                self.filter.push(i);
+    /// `:hook <command>` runs the shell command after entering the targeted repos, `:hook` on
+    /// its own removes it
+    pub fn hook_command(&mut self, cmd: &[&str]) -> Option<Input> {
+        let hook = cmd.join(" ").trim().to_string();
+        let targets = self.targets();
+        if targets.is_empty() {
+            return Some(Input::warning(String::from("No repo to set a hook for")));
+        }
+        for i in targets.iter() {
+            self.items.items[*i].on_enter = Some(hook.clone()).filter(|h| !h.is_empty());
+        }
+        let msg = if hook.is_empty() {
+            format!("Removed the hook of {} repos", targets.len())
+        } else {
+            format!("Running `{}` after entering {} repos", hook, targets.len())
+        };
+        Some(Input::info(msg))
+    }
+
+    /// `:enter <cd|env|editor>` sets what happens on entering a repo, on its own shows it
+    pub fn enter_command(&mut self, cmd: &[&str]) -> Option<Input> {
+        let text = cmd.join(" ");
+        if !text.trim().is_empty() {
+            match text.trim().parse() {
+                Ok(action) => self.settings.enter = action,
+                Err(e) => return Some(Input::error(e.to_string())),
             }
         }
-        info!(
-            "Filtering, matched {} repos",
-            self.items.items.len() - self.filter.len()
-        );
+        Some(Input::info(format!("Enter action: {}", self.settings.enter)))
+    }
+
+    pub fn enter_action(&self) -> EnterAction {
+        self.settings.enter
+    }
+
+    /// Repos batch commands run on: the selected repos, all the filtered ones, or the ones
+    /// under the cursor
+    fn batch_targets(&self) -> Vec<usize> {
+        if self.selected.is_empty() && self.filters.is_active() {
+            self.visible.clone()
+        } else {
+            self.targets()
+        }
+    }
+
+    /// Run a git operation on the batch targets in the background, e.g. `:fetch`
+    pub fn git_command(&mut self, cmd: &str, args: &[&str]) -> Option<Input> {
+        if self.batch_running() {
+            return Some(Input::warning(String::from("Wait for the running git operation")));
+        }
+        let op = match GitOp::parse(cmd, args) {
+            Ok(op) => op,
+            Err(e) => return Some(Input::error(e.to_string())),
+        };
+        let targets: Vec<(usize, String, std::path::PathBuf)> = self
+            .batch_targets()
+            .into_iter()
+            .map(|i| (i, self.items.items[i].name.clone(), self.items.items[i].path.clone()))
+            .collect();
+        if targets.is_empty() {
+            return Some(Input::warning(String::from("No repos to run on")));
+        }
+        info!("Running {} on {} repos", op, targets.len());
+        let msg = format!("Running {} on {} repos", op, targets.len());
+        self.batch = Some(Batch::start(op, targets));
+        Some(Input::info(msg))
+    }
+
+    pub fn batch_running(&self) -> bool {
+        self.batch.as_ref().map(|b| !b.is_done()).unwrap_or(false)
+    }
+
+    /// Refresh the repos the running batch finished with, returns a summary once it is done
+    pub fn poll_batch(&mut self) -> Option<Input> {
+        let batch = self.batch.as_mut()?;
+        if batch.is_done() {
+            return None;
+        }
+        for item in batch.poll() {
+            if let Err(e) = self.items.items[item].update() {
+                warn!("Could not refresh {}: {}", self.items.items[item].name, e);
+            }
+        }
+        if batch.is_done() {
+            Some(Input::info(batch.summary()))
+        } else {
+            None
+        }
+    }
+
+    /// Dismiss the popup of a finished batch, returns false if there was none
+    pub fn close_batch(&mut self) -> bool {
+        if self.batch.is_some() && !self.batch_running() {
+            self.batch = None;
+            return true;
+        }
+        false
+    }
+
+    /// `:/ <query>` narrows down the current filters, `:/` on its own clears them
+    pub fn filter_command(&mut self, cmd: &[&str]) -> Option<Input> {
+        let text = cmd.join(" ").trim().to_string();
+        if text.is_empty() {
+            self.clear_filters();
+            return None;
+        }
+        let query = match Query::parse(&text) {
+            Ok(q) => q,
+            Err(e) => return Some(Input::error(e.to_string())),
+        };
+        self.filters.push(text, query);
+        self.refilter();
+        info!("Filtering, matched {} repos", self.visible.len());
         None
     }
 
+    /// Filter while the command is being typed, invalid (e.g. half typed) queries are ignored
+    pub fn preview_filter(&mut self, cmd: &[&str]) {
+        if let Ok(query) = Query::parse(&cmd.join(" ")) {
+            self.filters.preview(query);
+            self.refilter();
+        }
+    }
+
+    pub fn cancel_preview(&mut self) {
+        if self.filters.cancel_preview() {
+            self.refilter();
+        }
+    }
+
+    /// Remove the most recently applied filter
+    pub fn pop_filter(&mut self) {
+        if self.filters.pop() {
+            info!("Removed filter, {} left", self.filters.describe());
+            self.refilter();
+        }
+    }
+
+    pub fn clear_filters(&mut self) {
+        info!("Clearing filters");
+        self.filters.clear();
+        self.refilter();
+    }
+
+    /// `:s/ <query>` (or `/<query>`) jumps to the next matching repo, with no query the search
+    /// is cleared.
+    pub fn search_command(&mut self, text: &str) -> Option<Input> {
+        let origin = match self.search_preview.take() {
+            Some((origin, _)) => origin,
+            None => self.items.selected(),
+        };
+        let text = text.trim();
+        if text.is_empty() {
+            self.search = None;
+            return None;
+        }
+        let query = match Query::parse(text) {
+            Ok(q) => q,
+            Err(e) => return Some(Input::error(e.to_string())),
+        };
+        self.set_search(text, query);
+        self.items.state.select(origin);
+        self.jump_to_match(true);
+        Some(self.search_status())
+    }
+
+    /// Jump to the first match of the search being typed, invalid queries are ignored
+    pub fn preview_search(&mut self, text: &str) {
+        if self.search_preview.is_none() {
+            self.search_preview = Some((self.items.selected(), self.search.take()));
+        }
+        let origin = self.search_preview.as_ref().and_then(|(origin, _)| *origin);
+        self.items.state.select(origin);
+        let text = text.trim();
+        match Query::parse(text) {
+            Ok(query) if !text.is_empty() => {
+                self.set_search(text, query);
+                self.jump_to_match(true);
+            }
+            Ok(_) => self.search = None,
+            Err(_) => {}
+        }
+    }
+
+    pub fn cancel_search_preview(&mut self) {
+        if let Some((origin, search)) = self.search_preview.take() {
+            self.items.state.select(origin);
+            self.search = search;
+        }
+    }
+
+    /// Move to the next (or previous) match of the last search, like `n`/`N` in vim
+    pub fn search_next(&mut self, forward: bool) -> Option<Input> {
+        if self.search.is_none() {
+            return Some(Input::warning(String::from("No previous search")));
+        }
+        self.jump_to_match(forward);
+        Some(self.search_status())
+    }
+
+    fn set_search(&mut self, text: &str, query: Query) {
+        self.search = Some(Search {
+            text: String::from(text),
+            query,
+            rows: vec![],
+        });
+        self.update_search_rows();
+    }
+
+    fn update_search_rows(&mut self) {
+        if let Some(search) = self.search.as_mut() {
+            let items = &self.items.items;
+            search.rows = self
+                .rows
+                .iter()
+                .enumerate()
+                .filter(|(_, r)| match r {
+                    Row::Repo { item, .. } => search.query.matches(&items[*item]),
+                    Row::Dir { .. } => false,
+                })
+                .map(|(row, _)| row)
+                .collect();
+        }
+    }
+
+    /// Move the cursor to the closest match after (or before) it, wrapping around
+    fn jump_to_match(&mut self, forward: bool) {
+        let rows = match self.search.as_ref() {
+            Some(s) if !s.rows.is_empty() => &s.rows,
+            _ => return,
+        };
+        let target = match (self.items.selected(), forward) {
+            (None, true) => rows.first(),
+            (None, false) => rows.last(),
+            (Some(curr), true) => rows.iter().find(|&&r| r > curr).or_else(|| rows.first()),
+            (Some(curr), false) => rows.iter().rev().find(|&&r| r < curr).or_else(|| rows.last()),
+        };
+        self.items.state.select(target.copied());
+    }
+
+    fn search_status(&self) -> Input {
+        let search = match self.search.as_ref() {
+            Some(s) => s,
+            None => return Input::default(),
+        };
+        if search.rows.is_empty() {
+            return Input::warning(format!("Pattern not found: {}", search.text));
+        }
+        let position = self
+            .items
+            .selected()
+            .and_then(|row| search.rows.iter().position(|&r| r == row))
+            .map(|p| (p + 1).to_string())
+            .unwrap_or_else(|| String::from("-"));
+        Input::info(format!("/{} [{}/{}]", search.text, position, search.rows.len()))
+    }
+
+    /// `:sort <keys>` sorts by the keys, e.g. `:sort status name`, and `:sort! <keys>` in reverse.
+    /// On their own `:sort` shows the current order and `:sort!` reverses it.
+    pub fn sort_command(&mut self, cmd: &[&str], reverse: bool) -> Option<Input> {
+        let text = cmd.join(" ");
+        if text.trim().is_empty() {
+            if reverse {
+                self.settings.sort.reverse = !self.settings.sort.reverse;
+                self.refilter();
+            }
+            return Some(self.sort_status());
+        }
+        match SortOrder::parse(&text) {
+            Ok(mut order) => {
+                order.reverse = reverse;
+                self.set_sort(order);
+                Some(self.sort_status())
+            }
+            Err(e) => Some(Input::error(e.to_string())),
+        }
+    }
+
+    /// Sort by the next key on its own
+    pub fn cycle_sort(&mut self) -> Input {
+        let mut order = self.settings.sort.clone();
+        order.cycle();
+        self.set_sort(order);
+        self.sort_status()
+    }
+
+    pub fn reverse_sort(&mut self) -> Input {
+        let mut order = self.settings.sort.clone();
+        order.reverse = !order.reverse;
+        self.set_sort(order);
+        self.sort_status()
+    }
+
+    fn set_sort(&mut self, order: SortOrder) {
+        info!("Sorting by {}", order);
+        self.settings.sort = order;
+        self.refilter();
+    }
+
+    fn sort_status(&self) -> Input {
+        Input::info(format!("Sorted by {}", self.settings.sort))
+    }
+
+    /// Item indices in the chosen sort order
+    fn sorted_items(&self) -> Vec<usize> {
+        let items = &self.items.items;
+        let mut sorted: Vec<usize> = (0..items.len()).collect();
+        sorted.sort_by(|&a, &b| self.settings.sort.compare(&items[a], &items[b]));
+        sorted
+    }
+
+    /// Recompute the visible rows from the filters, ranking fuzzy matches by score. Selected
+    /// repos that are filtered out are deselected.
+    fn refilter(&mut self) {
+        self.highlights.clear();
+        let sorted = self.sorted_items();
+        if !self.filters.is_active() {
+            self.visible = sorted;
+            self.rebuild_rows();
+            self.items.select_first(self.rows.len());
+            self.update_search_rows();
+            return;
+        }
+        let terms = self.filters.fuzzy_terms();
+        let mut ranked: Vec<(i64, usize)> = Vec::new();
+        for i in sorted {
+            let repo = &self.items.items[i];
+            if !self.filters.matches(repo) {
+                continue;
+            }
+            let mut score = 0;
+            let mut highlight = Vec::new();
+            for term in terms.iter() {
+                if let Some((m, is_name)) = best_match(term, repo) {
+                    score += m.score;
+                    if is_name {
+                        highlight.extend(m.positions);
+                    }
+                }
+            }
+            if !highlight.is_empty() {
+                self.highlights.insert(i, highlight);
+            }
+            ranked.push((score + recency_boost(repo), i));
+        }
+        if !terms.is_empty() {
+            // Stable, so equal scores keep the sort order
+            ranked.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+        }
+        self.visible = ranked.into_iter().map(|(_, i)| i).collect();
+        let visible = &self.visible;
+        self.selected.retain(|i| visible.contains(i));
+        self.rebuild_rows();
+        self.items.select_first(self.rows.len());
+        self.update_search_rows();
+    }
+
+    /// Lay out the visible repos as rows, under their directories in tree mode
+    fn rebuild_rows(&mut self) {
+        if !self.tree {
+            self.rows = self
+                .visible
+                .iter()
+                .map(|&item| Row::Repo { item, depth: 0 })
+                .collect();
+            return;
+        }
+        let items = &self.items.items;
+        let paths: Vec<&Path> = self.visible.iter().map(|&i| items[i].path.as_path()).collect();
+        let entries: Vec<(Vec<&str>, usize)> = TreeSorter::new()
+            .order(&paths)
+            .into_iter()
+            .map(|p| (sorting::components(paths[p]), self.visible[p]))
+            .collect();
+        let mut rows = Vec::new();
+        push_tree_rows(&entries, 0, Path::new("/"), 0, &self.collapsed, &mut rows);
+        self.rows = rows;
+    }
+
+    /// Switch between the flat (ranked) list and the directory tree
+    pub fn toggle_tree(&mut self) {
+        self.tree = !self.tree;
+        info!("Tree mode {}", self.tree);
+        self.rebuild_rows();
+        self.items.select_first(self.rows.len());
+        self.update_search_rows();
+    }
+
+    /// Fold or unfold the directory under the cursor, returns false if it isn't on one
+    pub fn toggle_dir(&mut self) -> bool {
+        let path = match self.curr_row() {
+            Some(Row::Dir { path, .. }) => path.clone(),
+            _ => return false,
+        };
+        if !self.collapsed.remove(&path) {
+            self.collapsed.insert(path.clone());
+        }
+        self.rebuild_rows();
+        // Stay on the directory
+        let row = self
+            .rows
+            .iter()
+            .position(|r| matches!(r, Row::Dir { path: p, .. } if *p == path));
+        self.items.state.select(row);
+        self.update_search_rows();
+        true
+    }
+
+    /// Select the repo under the cursor, or all repos under the directory
     pub fn select_current(&mut self) {
-        if let Some(s) = self.items.selected() {
+        for s in self.targets_under_cursor() {
             info!("Selecting {}", s);
-            utils::set_item_in_vec(&mut self.selected, s);
+            if !self.selected.contains(&s) {
+                utils::set_item_in_vec(&mut self.selected, s);
+            }
+        }
+    }
+
+    fn targets_under_cursor(&self) -> Vec<usize> {
+        match self.curr_row() {
+            Some(Row::Repo { item, .. }) => vec![*item],
+            Some(Row::Dir { items, .. }) => items.clone(),
+            None => vec![],
         }
     }
 
     pub fn select_range(&mut self) {
-        if self.select_mode {
+        if !self.select_mode {
             info!("Starting select range");
             self.select_mode = true;
             self.select_current();
         } else {
             info!("Exiting select range");
+            self.select_mode = false;
         }
     }
 
     pub fn toggle_expanded(&mut self) {
-        if let Some(s) = self.items.selected() {
+        if let Some(s) = self.curr_index() {
             utils::toggle_item_in_vec(&mut self.expanded, s);
+        } else {
+            self.toggle_dir();
         }
     }
 
     pub fn next(&mut self) {
-        self.items.next();
+        self.items.next(self.rows.len());
     }
 
     pub fn previous(&mut self) {
-        self.items.previous();
+        self.items.previous(self.rows.len());
     }
 }
 
 impl Draw for RepoView {
     fn draw<B: Backend>(&mut self, frame: &mut Frame<B>, area: Rect) {
+        // Widest name including its indent, so the columns line up in the tree too
+        let name_width = self
+            .rows
+            .iter()
+            .filter_map(|r| match r {
+                Row::Repo { item, depth } => {
+                    Some(self.items.items[*item].name.len() + 2 * *depth as usize)
+                }
+                Row::Dir { .. } => None,
+            })
+            .fold(self.column_widths[0], usize::max);
         let items: Vec<ListItem> = self
-            .items
-            .items
+            .rows
             .iter()
             .enumerate()
-            .filter_map(|(i, repo)| {
+            .map(|(row, r)| {
+                let (i, depth) = match r {
+                    Row::Repo { item, depth } => (*item, *depth),
+                    Row::Dir { path, label, depth, items } => {
+                        let repos = items.iter().map(|&i| &self.items.items[i]).collect();
+                        let dir = DirItem::new(label, *depth, !self.collapsed.contains(path), repos);
+                        let all_selected = items.iter().all(|i| self.selected.contains(i));
+                        let b_color = if all_selected {
+                            Color::Rgb(100, 100, 100)
+                        } else {
+                            Color::Reset
+                        };
+                        return ListItem::new(dir.text()).style(Style::default().bg(b_color));
+                    }
+                };
+                let repo = &self.items.items[i];
                 // TODO: Add selected as a param here, change color of Status for better
                 // rendering on background
-                if !self.filter.contains(&i) {
-                    let repo_view = RepoItem::new(
-                        &repo,
-                        self.column_widths[0],
-                        0,
-                        self.expanded.contains(&i),
-                        false,
-                        30u8,
-                    );
-                    let selected = self.selected.contains(&i);
-                    let (b_color, f_color) = if selected {
-                        (Color::Rgb(100, 100, 100), Color::White)
-                    } else {
-                        (Color::Reset, Color::White)
-                    };
-                    Some(
-                        ListItem::new(repo_view.text())
-                            .style(Style::default().bg(b_color).fg(f_color)),
-                    )
-                } else {
-                    None
-                }
+                let repo_view = RepoItem::new(
+                    repo,
+                    name_width,
+                    depth,
+                    self.expanded.contains(&i),
+                    self.highlights.get(&i).map(|h| h.as_slice()).unwrap_or(&[]),
+                );
+                let selected = self.selected.contains(&i);
+                let search_match = self
+                    .search
+                    .as_ref()
+                    .map(|s| s.rows.contains(&row))
+                    .unwrap_or(false);
+                let (b_color, f_color) = match (selected, search_match) {
+                    (true, _) => (Color::Rgb(100, 100, 100), Color::White),
+                    (false, true) => (Color::Reset, Color::Yellow),
+                    (false, false) => (Color::Reset, Color::White),
+                };
+                ListItem::new(repo_view.text())
+                    .style(Style::default().bg(b_color).fg(f_color))
             })
             .collect();
 
+        let mut title = if self.filters.is_active() {
+            format!(
+                "Repositories ({}/{}) [{}]",
+                self.visible.len(),
+                self.items.items.len(),
+                self.filters.describe()
+            )
+        } else {
+            String::from("Repositories")
+        };
+        if self.tree {
+            title.push_str(" as tree");
+        } else {
+            title.push_str(&format!(" by {}", self.settings.sort));
+        }
+
         // Create a List from all list items and highlight the currently selected one
         let items = List::new(items)
-            .block(Block::default().borders(Borders::ALL).title("Repositories"))
+            .block(Block::default().borders(Borders::ALL).title(title))
             .highlight_style(
                 Style::default()
                     .bg(Color::LightBlue)
@@ -166,6 +797,9 @@ impl Draw for RepoView {
                     .add_modifier(Modifier::BOLD),
             );
         frame.render_stateful_widget(items, area, &mut self.items.state);
+        if let Some(batch) = self.batch.as_mut() {
+            batch.draw(frame, area);
+        }
     }
 }
 // Basic stateful list from example on tui-rs
@@ -182,10 +816,15 @@ impl<T> StatefulList<T> {
         }
     }
 
-    fn next(&mut self) {
+    // Navigation is over the `len` rows shown, which may be fewer than the items
+    fn next(&mut self, len: usize) {
+        if len == 0 {
+            self.state.select(None);
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
-                if i >= self.items.len() - 1 {
+                if i >= len - 1 {
                     0
                 } else {
                     i + 1
@@ -196,11 +835,15 @@ impl<T> StatefulList<T> {
         self.state.select(Some(i));
     }
 
-    fn previous(&mut self) {
+    fn previous(&mut self, len: usize) {
+        if len == 0 {
+            self.state.select(None);
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
-                    self.items.len() - 1
+                    len - 1
                 } else {
                     i - 1
                 }
@@ -210,7 +853,51 @@ impl<T> StatefulList<T> {
         self.state.select(Some(i));
     }
 
+    fn select_first(&mut self, len: usize) {
+        self.state.select(if len == 0 { None } else { Some(0) });
+    }
+
     fn selected(&self) -> Option<usize> {
         self.state.selected()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn describe(rows: &[Row]) -> Vec<String> {
+        rows.iter()
+            .map(|r| match r {
+                Row::Repo { item, depth } => format!("{}{}", "  ".repeat(*depth as usize), item),
+                Row::Dir { label, depth, items, .. } => {
+                    format!("{}{}/ {:?}", "  ".repeat(*depth as usize), label, items)
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tree_rows() {
+        let entries: Vec<(Vec<&str>, usize)> = [
+            ("/w/a", 0),
+            ("/w/a/nested", 1),
+            ("/w/go/src/one", 2),
+            ("/w/go/src/two", 3),
+        ]
+        .iter()
+        .map(|(p, i)| (sorting::components(Path::new(p)), *i))
+        .collect();
+        let mut rows = Vec::new();
+        push_tree_rows(&entries, 0, Path::new("/"), 0, &HashSet::new(), &mut rows);
+        assert_eq!(
+            describe(&rows),
+            vec!["/w/ [0, 1, 2, 3]", "  0", "    1", "  go/src/ [2, 3]", "    2", "    3"]
+        );
+
+        let collapsed = [PathBuf::from("/w/go/src")].into_iter().collect();
+        let mut rows = Vec::new();
+        push_tree_rows(&entries, 0, Path::new("/"), 0, &collapsed, &mut rows);
+        assert_eq!(describe(&rows), vec!["/w/ [0, 1, 2, 3]", "  0", "    1", "  go/src/ [2, 3]"]);
+    }
+}
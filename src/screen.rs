@@ -1,7 +1,7 @@
 use crate::input::Input;
 use crate::repo::Repos;
 use crate::repoview::RepoView;
-use crate::utils::shell_file;
+use crate::shell::{self, Shell};
 use crossterm::{
     event::{
         self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
@@ -14,14 +14,11 @@ use std::{
     error::Error,
     io,
     time::{Duration, Instant},
-    fs,
 };
 use tui::{
     backend::{Backend, CrosstermBackend},
     layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Style},
     terminal::{Frame, Terminal},
-    widgets::{Block, Paragraph},
 };
 
 pub trait Draw {
@@ -35,7 +32,6 @@ pub struct Screen {
 
 impl Screen {
     pub fn new(repos: Repos) -> Self {
-        let longest = repos.longest_name();
         let repoview = RepoView::new(repos);
         Self {
             repoview,
@@ -43,6 +39,13 @@ impl Screen {
         }
     }
 
+    /// Start with `query` applied as a filter, e.g. when `rgm go` matched several repos
+    pub fn filter(&mut self, query: &str) {
+        if let Some(msg) = self.repoview.filter_command(&[query]) {
+            self.input = msg;
+        }
+    }
+
     fn get_terminal<W>(mut writer: W) -> Result<Terminal<CrosstermBackend<W>>, Box<dyn Error>>
     where
         W: io::Write,
@@ -63,6 +66,9 @@ impl Screen {
         let tick_rate = Duration::from_millis(250);
         let mut last_tick = Instant::now();
         loop {
+            if let Some(msg) = self.repoview.poll_batch() {
+                self.input = msg;
+            }
             self.draw(&mut terminal);
             let timeout = tick_rate
                 .checked_sub(last_tick.elapsed())
@@ -100,10 +106,7 @@ impl Screen {
                 self.input.draw(f, chunks[1]);
             }
         });
-        match res {
-            Ok(_) => true,
-            Err(_) => false,
-        }
+        res.is_ok()
     }
 
     fn exit<B>(&self, terminal: &mut Terminal<B>) -> Result<(), Box<dyn Error>>
@@ -120,7 +123,7 @@ impl Screen {
         let raw_evt = match event::read() {
             Ok(evt) => evt,
             Err(e) => {
-                println!("{}", e.to_string());
+                println!("{}", e);
                 return true;
             }
         };
@@ -138,11 +141,19 @@ impl Screen {
 
             if self.input.is_editing() {
                 match key.code {
-                    KeyCode::Char(x) => self.input.push(x),
+                    KeyCode::Char(x) => {
+                        self.input.push(x);
+                        self.preview_command();
+                    }
                     KeyCode::Enter => self.parse_command(),
-                    KeyCode::Backspace => self.input.pop(),
+                    KeyCode::Backspace => {
+                        self.input.pop();
+                        self.preview_command();
+                    }
                     KeyCode::Esc => {
                         info!("Leaving command mode");
+                        self.repoview.cancel_preview();
+                        self.repoview.cancel_search_preview();
                         self.input.editing(false);
                         self.input.clear();
                     }
@@ -150,10 +161,29 @@ impl Screen {
                 }
             } else {
                 match key.code {
+                    KeyCode::Char('q') if self.repoview.batch_running() => {
+                        self.input = Input::warning(String::from(
+                            "Git operations are still running, wait or use Ctrl-C",
+                        ));
+                    }
                     KeyCode::Char('q') => return true,
                     KeyCode::Char('v') => self.repoview.select_current(),
                     KeyCode::Char('V') => self.repoview.select_range(),
-                    KeyCode::Char(':') | KeyCode::Char('/') => self.input.editing(true),
+                    KeyCode::Char(':') => self.input.editing(true),
+                    KeyCode::Char('/') => self.input.prompt('/'),
+                    KeyCode::Char('t') => self.repoview.toggle_tree(),
+                    KeyCode::Char('s') => self.input = self.repoview.cycle_sort(),
+                    KeyCode::Char('S') => self.input = self.repoview.reverse_sort(),
+                    KeyCode::Char('n') => {
+                        if let Some(i) = self.repoview.search_next(true) {
+                            self.input = i;
+                        }
+                    }
+                    KeyCode::Char('N') => {
+                        if let Some(i) = self.repoview.search_next(false) {
+                            self.input = i;
+                        }
+                    }
                     //KeyCode::Char('a') => self.apply_alias(),
                     KeyCode::Down => {
                         if self.repoview.select_mode {
@@ -168,12 +198,27 @@ impl Screen {
                         self.repoview.previous();
                     }
                     KeyCode::Left | KeyCode::Right => self.repoview.toggle_expanded(),
-                    KeyCode::Enter => {
-                        if let Err(e) = self.write_shell_script(){
+                    KeyCode::Esc if self.repoview.close_batch() => {}
+                    KeyCode::Esc => self.repoview.pop_filter(),
+                    // Enter folds directories in the tree
+                    KeyCode::Enter if self.repoview.toggle_dir() => {}
+                    // Entering a repo closes rgm, which would abandon the batch half way
+                    KeyCode::Enter if self.repoview.batch_running() => {
+                        self.input = Input::warning(String::from(
+                            "Git operations are still running, wait or use Ctrl-C",
+                        ));
+                    }
+                    KeyCode::Enter => match self.write_shell_script() {
+                        Ok(()) => {
+                            self.repoview.enter();
+                            return true
+                        }
+                        // Stay open, closing would just drop the user back where they were
+                        Err(e) => {
                             error!("{:?}", e);
+                            self.input = Input::error(e.to_string());
                         }
-                        return true
-                    }
+                    },
                     _ => {}
                 }
             }
@@ -181,26 +226,45 @@ impl Screen {
         false
     }
 
-    fn write_shell_script(&self) -> std::io::Result<()>{
+    fn write_shell_script(&self) -> crate::error::Result<()>{
         if self.repoview.curr().is_none(){
             error!("Cannot write shell script for current selected. None selected");
             return Ok(())
         }
-        let shell_file = shell_file();
         let repo = self.repoview.curr().unwrap();
-        fs::write(
-            &shell_file,
-            format!("#!/bin/sh\ncd {}", repo.path.display())
-        )?;
-        Ok(())
+        let script = shell::enter_script(Shell::current(), repo, self.repoview.enter_action());
+        shell::write_script(&script)
+    }
+
+    /// Input text as a `:` command, `/<query>` is short for `:s/ <query>`
+    fn command_text(&self) -> String {
+        let input = self.input.text();
+        match input.strip_prefix('/') {
+            Some(query) => format!(":s/ {}", query),
+            None => input,
+        }
+    }
+
+    /// Show the effect of the command being typed, filters and searches update as you type
+    fn preview_command(&mut self) {
+        let input = self.command_text();
+        let cmd_str: Vec<&str> = input.split(' ').collect();
+        match cmd_str[0] {
+            ":/" | ":f/" => self.repoview.preview_filter(&cmd_str[1..]),
+            ":s/" => self.repoview.preview_search(&cmd_str[1..].join(" ")),
+            _ => {
+                self.repoview.cancel_preview();
+                self.repoview.cancel_search_preview();
+            }
+        }
     }
 
     fn parse_command(&mut self) {
         // Command format: `:<command> <args>`
-        let input = self.input.text();
+        let input = self.command_text();
         info!("Parsing command {:?}", &input);
-        let cmd_str: Vec<&str> = input.split(" ").collect();
-        if cmd_str.len() < 1 {
+        let cmd_str: Vec<&str> = input.split(' ').collect();
+        if cmd_str.is_empty() {
             self.input = Input::warning(String::from("No command"));
             return;
         }
@@ -214,9 +278,16 @@ impl Screen {
         };
         match cmd_str[0] {
             ":/" | ":f/" => handle_cmd(self.repoview.filter_command(&cmd_str[1..])),
-            ":s/" => unimplemented!(), // Search
+            ":s/" => handle_cmd(self.repoview.search_command(&cmd_str[1..].join(" "))),
             ":t" => handle_cmd(self.repoview.tag_command(&cmd_str[1..])),
             ":a" => handle_cmd(self.repoview.alias_command(&cmd_str[1..])),
+            ":sort" => handle_cmd(self.repoview.sort_command(&cmd_str[1..], false)),
+            ":sort!" => handle_cmd(self.repoview.sort_command(&cmd_str[1..], true)),
+            ":enter" => handle_cmd(self.repoview.enter_command(&cmd_str[1..])),
+            ":hook" => handle_cmd(self.repoview.hook_command(&cmd_str[1..])),
+            ":fetch" | ":pull" | ":push" | ":checkout" | ":co" => {
+                handle_cmd(self.repoview.git_command(&cmd_str[0][1..], &cmd_str[1..]))
+            }
             _ => self.input.editing(false),
         }
         self.repoview.reset_selected();
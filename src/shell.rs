@@ -0,0 +1,223 @@
+// Scripts for the shell wrapper to source after rgm exits, e.g. to cd into a repo
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::{Result, RgmError};
+use crate::repo::Repo;
+
+/// Shell the wrapper function runs in, told to us through `RGM_SHELL`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    // sh, bash and zsh
+    Posix,
+    Fish,
+    Nu,
+}
+
+impl Shell {
+    /// The shell from `RGM_SHELL`, POSIX when it is unset or unknown
+    pub fn current() -> Self {
+        match env::var("RGM_SHELL").as_deref() {
+            Ok("fish") => Shell::Fish,
+            Ok("nu") => Shell::Nu,
+            _ => Shell::Posix,
+        }
+    }
+
+    /// Quote `s` as a single word, nothing in it is expanded
+    pub fn quote(&self, s: &str) -> String {
+        let safe = |c: char| c.is_ascii_alphanumeric() || "_-./@%+=:,".contains(c);
+        if !s.is_empty() && s.chars().all(safe) {
+            return String::from(s);
+        }
+        match self {
+            // Nothing is special inside single quotes, a quote ends them so it is spliced in
+            Shell::Posix => format!("'{}'", s.replace('\'', r"'\''")),
+            // Backslashes and quotes are the only escapes inside single quotes
+            Shell::Fish => format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'")),
+            // Nushell is handed JSON by `enter_script`, it never gets a script to quote for
+            Shell::Nu => unreachable!("nothing is quoted for nushell"),
+        }
+    }
+
+    /// Command sourcing the script at `path` into the current shell
+    fn source(&self, path: &Path) -> String {
+        let path = self.quote(&path.to_string_lossy());
+        match self {
+            Shell::Posix => format!(". {}", path),
+            Shell::Fish => format!("source {}", path),
+            // No virtualenv activation for nushell, see `activate_script`
+            Shell::Nu => unreachable!("nothing is sourced by nushell"),
+        }
+    }
+
+    /// Activation script of a virtualenv, relative to its root. Nushell only activates them
+    /// with `overlay use`, which can't take a path chosen at runtime.
+    fn activate_script(&self) -> Option<&'static str> {
+        match self {
+            Shell::Posix => Some("bin/activate"),
+            Shell::Fish => Some("bin/activate.fish"),
+            Shell::Nu => None,
+        }
+    }
+}
+
+/// What happens after entering a repo, on top of changing into it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EnterAction {
+    #[default]
+    Cd,
+    // Activate the repo's virtualenv, or start its nix shell
+    Env,
+    // Open $VISUAL or $EDITOR in the repo
+    Editor,
+}
+
+impl FromStr for EnterAction {
+    type Err = RgmError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "cd" => Ok(EnterAction::Cd),
+            "env" => Ok(EnterAction::Env),
+            "editor" => Ok(EnterAction::Editor),
+            _ => Err(RgmError {
+                message: format!("Unknown action '{}', expected one of cd, env, editor", s),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for EnterAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            EnterAction::Cd => "cd",
+            EnterAction::Env => "env",
+            EnterAction::Editor => "editor",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Virtualenv activation if the repo has one, otherwise its nix shell
+fn env_command(shell: Shell, dir: &Path) -> Option<String> {
+    for venv in [".venv", "venv"] {
+        let script = match shell.activate_script() {
+            Some(script) => dir.join(venv).join(script),
+            None => break,
+        };
+        if script.is_file() {
+            return Some(shell.source(&script));
+        }
+    }
+    if dir.join("flake.nix").is_file() {
+        return Some(String::from("nix develop"));
+    }
+    if dir.join("shell.nix").is_file() || dir.join("default.nix").is_file() {
+        return Some(String::from("nix-shell"));
+    }
+    None
+}
+
+/// The user's editor command, it may come with its own arguments so it is used as is
+fn editor() -> String {
+    env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| String::from("vi"))
+}
+
+/// Script entering `repo`: cd into it, run `action`, then the repo's own hook if it has one.
+///
+/// Nushell gets a JSON record of the directory and commands instead, its wrapper runs them.
+pub fn enter_script(shell: Shell, repo: &Repo, action: EnterAction) -> String {
+    let dir = repo.path.to_string_lossy();
+    let mut run = Vec::new();
+    match action {
+        EnterAction::Cd => {}
+        EnterAction::Env => run.extend(env_command(shell, &repo.path)),
+        EnterAction::Editor => run.push(format!("{} .", editor())),
+    }
+    // Typed in by the user as shell code, so not quoted
+    run.extend(repo.on_enter.clone());
+    if shell == Shell::Nu {
+        return format!("{}\n", json!({ "cd": dir, "run": run }));
+    }
+    let mut lines = vec![format!("cd {}", shell.quote(&dir))];
+    lines.extend(run);
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// Have the shell wrapper run `script` once rgm exits, through the file it passed in
+/// `RGM_OUTPUT`. Every call of the wrapper gets its own, so shells don't see each other's.
+pub fn write_script(script: &str) -> Result<()> {
+    match env::var_os("RGM_OUTPUT").filter(|p| !p.is_empty()) {
+        Some(path) => Ok(fs::write(path, script)?),
+        None => Err(RgmError {
+            message: String::from(
+                "Not run through the rgm shell function, set it up with `rgm-bin init <shell>`",
+            ),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::{repo, test_dir};
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(Shell::Posix.quote("/src/api-server"), "/src/api-server");
+        assert_eq!(Shell::Posix.quote(""), "''");
+        assert_eq!(Shell::Posix.quote("/src/my repo"), "'/src/my repo'");
+        assert_eq!(Shell::Posix.quote("/src/$(rm -rf ~)"), "'/src/$(rm -rf ~)'");
+        assert_eq!(Shell::Posix.quote("it's"), r"'it'\''s'");
+        assert_eq!(Shell::Fish.quote("it's"), r"'it\'s'");
+        assert_eq!(Shell::Fish.quote(r"a\b"), r"'a\\b'");
+    }
+
+    #[test]
+    fn test_enter_script() {
+        // A space in the path, it has to be quoted
+        let dir = test_dir("shell dir");
+        fs::create_dir_all(dir.join(".venv/bin")).unwrap();
+        fs::write(dir.join(".venv/bin/activate"), "").unwrap();
+        let mut venv = repo("venv", &dir, &[]);
+        let cd = format!("cd '{}'", dir.display());
+        assert_eq!(enter_script(Shell::Posix, &venv, EnterAction::Cd), format!("{}\n", cd));
+        assert_eq!(
+            enter_script(Shell::Posix, &venv, EnterAction::Env),
+            format!("{}\n. '{}'\n", cd, dir.join(".venv/bin/activate").display())
+        );
+        // No activate.fish, and no nix files either
+        venv.on_enter = Some(String::from("git status -s"));
+        assert_eq!(
+            enter_script(Shell::Fish, &venv, EnterAction::Env),
+            format!("{}\ngit status -s\n", cd)
+        );
+        fs::write(dir.join("flake.nix"), "").unwrap();
+        assert!(enter_script(Shell::Fish, &venv, EnterAction::Env).contains("\nnix develop\n"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let plain = repo("api", "/src/api", &[]);
+        assert_eq!(enter_script(Shell::Posix, &plain, EnterAction::Cd), "cd /src/api\n");
+        assert_eq!(
+            enter_script(Shell::Nu, &plain, EnterAction::Editor).trim(),
+            json!({ "cd": "/src/api", "run": [format!("{} .", editor())] }).to_string()
+        );
+    }
+
+    #[test]
+    fn test_enter_action() {
+        assert_eq!("Editor".parse::<EnterAction>().unwrap(), EnterAction::Editor);
+        assert!("vim".parse::<EnterAction>().is_err());
+    }
+}
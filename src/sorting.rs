@@ -1,5 +1,11 @@
-use crate::repo::Repos;
-use std::path::Component;
+use crate::error::{Result, RgmError};
+use crate::repo::{Repo, Repos, Status, Tracking};
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Component, Path};
+use std::str::FromStr;
 use indexmap::{IndexMap, IndexSet};
 
 pub trait Sort {
@@ -16,6 +22,206 @@ impl Sort for AlphabeticalSorter {
     }
 }
 
+/// Most recent HEAD commit first, repos without commits last
+pub struct RecentCommitSorter;
+
+impl Sort for RecentCommitSorter {
+    fn sort(&mut self, repos: Repos) -> Repos {
+        let mut repos = repos;
+        repos
+            .repos
+            .sort_by_key(|r| Reverse(r.last_commit.as_ref().map(|c| c.time)));
+        repos
+    }
+}
+
+/// Most recent local activity first, see `Repo::last_activity`
+pub struct RecentActivitySorter;
+
+impl Sort for RecentActivitySorter {
+    fn sort(&mut self, repos: Repos) -> Repos {
+        let mut repos = repos;
+        repos.repos.sort_by_key(|r| Reverse(r.last_activity()));
+        repos
+    }
+}
+
+/// What repos can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortKey {
+    Name,
+    // Repos that need attention first
+    Status,
+    Path,
+    // Most recent activity first
+    Recent,
+    // By first tag, untagged repos last
+    Tag,
+}
+
+impl SortKey {
+    /// Keys in the order the TUI cycles through them
+    pub const ALL: [SortKey; 5] = [
+        SortKey::Recent,
+        SortKey::Name,
+        SortKey::Status,
+        SortKey::Path,
+        SortKey::Tag,
+    ];
+
+    fn compare(&self, a: &Repo, b: &Repo) -> Ordering {
+        match self {
+            SortKey::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            SortKey::Status => status_rank(a.status.as_ref()).cmp(&status_rank(b.status.as_ref())),
+            SortKey::Path => a.path.cmp(&b.path),
+            SortKey::Recent => Reverse(a.last_activity()).cmp(&Reverse(b.last_activity())),
+            SortKey::Tag => {
+                let first_tag = |r: &Repo| r.tags.iter().map(|t| t.to_lowercase()).min();
+                match (first_tag(a), first_tag(b)) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = RgmError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "name" => Ok(SortKey::Name),
+            "status" => Ok(SortKey::Status),
+            "path" => Ok(SortKey::Path),
+            "recent" => Ok(SortKey::Recent),
+            "tag" | "tags" => Ok(SortKey::Tag),
+            _ => Err(RgmError {
+                message: format!(
+                    "Unknown sort '{}', expected one of name, status, path, recent, tag",
+                    s
+                ),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SortKey::Name => "name",
+            SortKey::Status => "status",
+            SortKey::Path => "path",
+            SortKey::Recent => "recent",
+            SortKey::Tag => "tag",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How much a status needs attention, lowest first
+fn status_rank(status: Option<&Status>) -> u8 {
+    let status = match status {
+        Some(s) => s,
+        None => return 10,
+    };
+    if status.operation.is_some() {
+        return 0;
+    }
+    if status.changes.conflicted > 0 {
+        return 1;
+    }
+    match status.tracking {
+        Tracking::Diverged(_, _) => 2,
+        Tracking::UpstreamGone => 3,
+        _ if !status.changes.is_clean() => 4,
+        Tracking::Behind(_) => 5,
+        Tracking::Ahead(_) => 6,
+        Tracking::Detached => 7,
+        Tracking::Unborn | Tracking::NoUpstream => 8,
+        Tracking::Even | Tracking::Bare => 9,
+    }
+}
+
+/// Sort by one key, then the next for ties, optionally reversed as a whole
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SortOrder {
+    pub keys: Vec<SortKey>,
+    pub reverse: bool,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        SortOrder {
+            keys: vec![SortKey::Recent],
+            reverse: false,
+        }
+    }
+}
+
+impl SortOrder {
+    /// Keys separated by spaces or commas, e.g. `status,name`
+    pub fn parse(text: &str) -> Result<Self> {
+        let keys = text
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|k| !k.is_empty())
+            .map(SortKey::from_str)
+            .collect::<Result<Vec<SortKey>>>()?;
+        if keys.is_empty() {
+            return Err(RgmError {
+                message: String::from("No sort keys given"),
+            });
+        }
+        Ok(SortOrder {
+            keys,
+            reverse: false,
+        })
+    }
+
+    pub fn compare(&self, a: &Repo, b: &Repo) -> Ordering {
+        let ord = self
+            .keys
+            .iter()
+            .map(|k| k.compare(a, b))
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or(Ordering::Equal);
+        if self.reverse {
+            ord.reverse()
+        } else {
+            ord
+        }
+    }
+
+    /// Sort by the key after the current first one, on its own
+    pub fn cycle(&mut self) {
+        let curr = self.keys.first().and_then(|k| SortKey::ALL.iter().position(|a| a == k));
+        let next = curr.map(|i| (i + 1) % SortKey::ALL.len()).unwrap_or(0);
+        self.keys = vec![SortKey::ALL[next]];
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let keys: Vec<String> = self.keys.iter().map(|k| k.to_string()).collect();
+        write!(f, "{}", keys.join(", "))?;
+        if self.reverse {
+            write!(f, " (reversed)")?;
+        }
+        Ok(())
+    }
+}
+
+impl Sort for SortOrder {
+    fn sort(&mut self, repos: Repos) -> Repos {
+        let mut repos = repos;
+        repos.repos.sort_by(|a, b| self.compare(a, b));
+        repos
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Hash)]
 pub enum Direction {
     Incoming,
@@ -43,7 +249,7 @@ impl PathTree {
     }
 
     pub fn add_node(&mut self, node: String) {
-        self.nodes.entry(node).or_insert(IndexSet::new());
+        self.nodes.entry(node).or_default();
     }
 
     pub fn add_edge(&mut self, node_a: String, node_b: String) {
@@ -73,30 +279,78 @@ impl PathTree {
     }
 }
 
-struct TreeSorter {
+/// Names of the directories (and file) making up `path`, without the root
+pub fn components(path: &Path) -> Vec<&str> {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(v) => v.to_str(),
+            _ => None
+        })
+        .collect()
+}
+
+/// Orders repos by their place in the directory tree: depth first, siblings by name, and a
+/// repo before the repos nested in it.
+pub struct TreeSorter {
     tree: PathTree
 }
 
+impl TreeSorter {
+    pub fn new() -> Self {
+        TreeSorter {
+            tree: PathTree::with_capacity(0)
+        }
+    }
 
-impl Sort for TreeSorter {
-    fn sort(&mut self, repos: Repos) -> Repos{
-        for repo in repos.repos.iter() {
-            // Build tree
-            let components = repo.path
-                .components()
-                .filter_map(|c| match c {
-                    Component::Normal(v) => v.to_str(),
-                    _ => None
-                })
-                .collect::<Vec<&'_ str>>();
-            println!("{:?}", components);
+    /// Indices into `paths` in tree order
+    pub fn order(&mut self, paths: &[&Path]) -> Vec<usize> {
+        self.tree = PathTree::with_capacity(paths.len());
+        let mut at_node: HashMap<String, Vec<usize>> = HashMap::new();
+        for (idx, path) in paths.iter().enumerate() {
+            let components = components(path);
             for i in 1..components.len()+1 {
                 let parent = components[0..i-1].join("/");
                 let node = components[0..i].join("/");
                 self.tree.add_edge(parent, node);
             }
+            at_node.entry(components.join("/")).or_default().push(idx);
+        }
+
+        let mut order = Vec::with_capacity(paths.len());
+        let mut stack = vec![String::new()];
+        while let Some(node) = stack.pop() {
+            if let Some(idx) = at_node.remove(&node) {
+                order.extend(idx);
+            }
+            let mut children: Vec<&str> = match self.tree.nodes().get(&node) {
+                Some(edges) => edges
+                    .iter()
+                    .filter(|e| e.direction == Direction::Outgoing)
+                    .map(|e| e.name.as_str())
+                    .collect(),
+                None => vec![],
+            };
+            // Children share the parent's prefix, so this sorts them by name
+            children.sort_unstable();
+            stack.extend(children.into_iter().rev().map(String::from));
         }
-        // Use sort_by and the path order from the TreePath
+        order
+    }
+}
+
+impl Default for TreeSorter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sort for TreeSorter {
+    fn sort(&mut self, repos: Repos) -> Repos{
+        let mut repos = repos;
+        let paths: Vec<&Path> = repos.repos.iter().map(|r| r.path.as_path()).collect();
+        let order = self.order(&paths);
+        let mut slots: Vec<Option<Repo>> = repos.repos.drain(..).map(Some).collect();
+        repos.repos = order.into_iter().filter_map(|i| slots[i].take()).collect();
         repos
     }
 }
@@ -104,6 +358,7 @@ impl Sort for TreeSorter {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::repo::{CommitInfo, Meta};
     use std::path::PathBuf;
 
     fn tree_with_paths(paths: Vec<PathBuf>) -> PathTree {
@@ -125,6 +380,92 @@ mod test {
         tree
     }
 
+    #[test]
+    fn test_recent_sorters(){
+        let repo = |name: &str, commit: Option<u64>, modified: Option<u64>| Repo {
+            name: String::from(name),
+            last_commit: commit.map(|time| CommitInfo {
+                summary: String::new(),
+                author: String::new(),
+                time,
+            }),
+            worktree_modified: modified,
+            ..Repo::default()
+        };
+        let repos = || {
+            Repos::new(
+                vec![
+                    repo("old", Some(10), Some(10)),
+                    repo("none", None, None),
+                    repo("committed", Some(30), Some(20)),
+                    repo("edited", Some(20), Some(40)),
+                ],
+                Meta::default(),
+            )
+        };
+        let names = |repos: Repos| repos.repos.into_iter().map(|r| r.name).collect::<Vec<_>>();
+        assert_eq!(
+            names(RecentCommitSorter.sort(repos())),
+            vec!["committed", "edited", "old", "none"]
+        );
+        assert_eq!(
+            names(RecentActivitySorter.sort(repos())),
+            vec!["edited", "committed", "old", "none"]
+        );
+    }
+
+    #[test]
+    fn test_sort_order(){
+        let repo = |name: &str, tags: &[&str], modified: Option<u64>| Repo {
+            name: String::from(name),
+            tags: tags.iter().map(|t| String::from(*t)).collect(),
+            worktree_modified: modified,
+            ..Repo::default()
+        };
+        let repos = || {
+            Repos::new(
+                vec![
+                    repo("b", &["web"], Some(10)),
+                    repo("C", &[], Some(30)),
+                    repo("a", &["api"], None),
+                    repo("d", &["web"], Some(20)),
+                ],
+                Meta::default(),
+            )
+        };
+        let names = |mut order: SortOrder| {
+            order.sort(repos()).repos.into_iter().map(|r| r.name).collect::<Vec<_>>()
+        };
+        assert_eq!(names(SortOrder::parse("name").unwrap()), vec!["a", "b", "C", "d"]);
+        assert_eq!(names(SortOrder::default()), vec!["C", "d", "b", "a"]);
+        assert_eq!(names(SortOrder::parse("tag, recent").unwrap()), vec!["a", "d", "b", "C"]);
+        let mut reversed = SortOrder::parse("tag recent").unwrap();
+        reversed.reverse = true;
+        assert_eq!(names(reversed), vec!["C", "b", "d", "a"]);
+        assert!(SortOrder::parse("size").is_err());
+
+        let mut order = SortOrder::default();
+        order.cycle();
+        assert_eq!(order.keys, vec![SortKey::Name]);
+    }
+
+    #[test]
+    fn test_tree_sorter(){
+        let repos = Repos::new(
+            ["/w/b", "/w/a/y", "/x", "/w/a", "/w/a/x", "/w/a-b"]
+                .iter()
+                .map(|p| Repo {
+                    path: PathBuf::from(p),
+                    ..Repo::default()
+                })
+                .collect(),
+            Meta::default(),
+        );
+        let sorted = TreeSorter::new().sort(repos);
+        let paths: Vec<&str> = sorted.repos.iter().map(|r| r.path.to_str().unwrap()).collect();
+        assert_eq!(paths, vec!["/w/a", "/w/a/x", "/w/a/y", "/w/a-b", "/w/b", "/x"]);
+    }
+
     #[test]
     fn test_tree_default(){
         let paths = vec![
@@ -0,0 +1,194 @@
+// Crash safe reading and writing of the repo store
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process;
+use fs2::FileExt;
+use log::warn;
+
+use crate::error::{Result, RgmError};
+
+/// `<file>.<suffix>` next to `path`
+pub fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Location of the rolling backup kept for `path`
+pub fn backup_file(path: &Path) -> PathBuf {
+    sibling(path, "bak")
+}
+
+/// Advisory lock on the store, held for a whole read-modify-write and released on drop.
+#[derive(Debug)]
+pub struct Lock {
+    file: File,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+/// Take the lock for `path`, waiting for any other rgm process holding it.
+///
+/// A separate lock file is used since the store itself gets replaced on every write.
+pub fn lock(path: &Path) -> Result<Lock> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(sibling(path, "lock"))?;
+    if file.try_lock_exclusive().is_err() {
+        eprintln!("Waiting for another rgm process to release {}", path.display());
+        file.lock_exclusive()?;
+    }
+    Ok(Lock { file })
+}
+
+fn is_valid_json(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|c| serde_json::from_str::<serde_json::Value>(&c).ok())
+        .is_some()
+}
+
+fn sync_dir(path: &Path) {
+    if let Some(dir) = path.parent() {
+        // Directories can't be opened for syncing on every platform, best effort only
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+}
+
+/// Atomically replace `path` with `contents`.
+///
+/// The new contents are written and synced to a temporary file in the same directory which is
+/// then renamed over `path`, so readers see either the old or the new file, never a mix. The
+/// previous file is kept as a backup, as long as it was valid.
+pub fn write(path: &Path, contents: &str) -> Result<()> {
+    let tmp = sibling(path, &format!("tmp-{}", process::id()));
+    let res = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+
+        // Don't rotate a corrupted file over a good backup
+        if is_valid_json(path) {
+            let backup_tmp = sibling(path, &format!("bak-{}", process::id()));
+            fs::copy(path, &backup_tmp)?;
+            fs::rename(&backup_tmp, backup_file(path))?;
+        }
+        fs::rename(&tmp, path)?;
+        sync_dir(path);
+        Ok(())
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res
+}
+
+/// Read and `parse` the file at `path`, falling back to its backup if it is missing or can't
+/// be parsed.
+pub fn read<T, P>(path: &Path, parse: P) -> Result<T>
+where
+    P: Fn(&str) -> Result<T>,
+{
+    let err = match fs::read_to_string(path) {
+        Ok(contents) => match parse(&contents) {
+            Ok(v) => return Ok(v),
+            Err(e) => e,
+        },
+        Err(e) => RgmError::from(e),
+    };
+    let backup = backup_file(path);
+    if !backup.exists() {
+        return Err(err);
+    }
+    let contents = fs::read_to_string(&backup)?;
+    let value = parse(&contents).map_err(|e| RgmError {
+        message: format!("{} (backup {} is unusable too: {})", err, backup.display(), e),
+    })?;
+    let msg = format!(
+        "Could not read {} ({}), loaded backup {} instead",
+        path.display(),
+        err,
+        backup.display()
+    );
+    warn!("{}", msg);
+    eprintln!("Warning: {}", msg);
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::testutil::test_dir;
+
+    fn test_file(name: &str) -> PathBuf {
+        test_dir(&format!("store-{}", name)).join("rgm.conf")
+    }
+
+    fn parse(contents: &str) -> Result<serde_json::Value> {
+        Ok(serde_json::from_str(contents)?)
+    }
+
+    #[test]
+    fn test_write_shorter_contents() {
+        let path = test_file("shorter");
+        write(&path, r#"{"repos": [1, 2, 3, 4, 5, 6]}"#).unwrap();
+        write(&path, r#"{"repos": []}"#).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), r#"{"repos": []}"#);
+        assert_eq!(
+            fs::read_to_string(backup_file(&path)).unwrap(),
+            r#"{"repos": [1, 2, 3, 4, 5, 6]}"#
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_read_falls_back_to_backup() {
+        let path = test_file("fallback");
+        write(&path, r#"{"a": 1}"#).unwrap();
+        write(&path, r#"{"a": 2}"#).unwrap();
+        fs::write(&path, r#"{"a": 2"#).unwrap();
+        assert_eq!(read(&path, parse).unwrap()["a"], 1);
+
+        // A corrupted file is never rotated over the backup
+        write(&path, r#"{"a": 3}"#).unwrap();
+        assert_eq!(read(&path, parse).unwrap()["a"], 3);
+        fs::write(&path, "").unwrap();
+        assert_eq!(read(&path, parse).unwrap()["a"], 1);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_lock_released_on_drop() {
+        let path = test_file("lock");
+        let lock = lock(&path).unwrap();
+        let other = File::open(sibling(&path, "lock")).unwrap();
+        assert!(other.try_lock_exclusive().is_err());
+        drop(lock);
+        assert!(other.try_lock_exclusive().is_ok());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_read_missing() {
+        let path = test_file("missing");
+        assert!(read(&path, parse).is_err());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,64 @@
+// Fixtures shared by the unit tests: scratch directories, git repos and `Repo`s
+use git2::{Oid, Repository, Signature};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::repo::Repo;
+
+/// Fresh, empty directory under the system temp dir. `name` has to be unique across all
+/// tests, they run in parallel.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rgm-test-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.canonicalize().unwrap()
+}
+
+fn signature() -> Signature<'static> {
+    Signature::now("rgm", "rgm@example.com").unwrap()
+}
+
+/// Init a git repo at `path` with a single empty commit
+pub fn init_repo(path: &Path) -> Repository {
+    let raw = Repository::init(path).unwrap();
+    {
+        let tree_id = raw.index().unwrap().write_tree().unwrap();
+        let tree = raw.find_tree(tree_id).unwrap();
+        raw.commit(Some("HEAD"), &signature(), &signature(), "init", &tree, &[]).unwrap();
+    }
+    raw
+}
+
+/// Add an empty commit on top of HEAD
+pub fn commit(raw: &Repository, message: &str) -> Oid {
+    let parent = raw.head().unwrap().peel_to_commit().unwrap();
+    let tree = parent.tree().unwrap();
+    raw.commit(Some("HEAD"), &signature(), &signature(), message, &tree, &[&parent]).unwrap()
+}
+
+/// Commit a new file named `file` on top of HEAD, or as the first commit
+pub fn commit_file(raw: &Repository, file: &str) -> Oid {
+    fs::write(raw.workdir().unwrap().join(file), file).unwrap();
+    let mut index = raw.index().unwrap();
+    // Another handle may have changed it on disk
+    index.read(true).unwrap();
+    index.add_path(Path::new(file)).unwrap();
+    index.write().unwrap();
+    let tree = raw.find_tree(index.write_tree().unwrap()).unwrap();
+    let parent = raw.head().ok().and_then(|h| h.peel_to_commit().ok());
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    raw.commit(Some("HEAD"), &signature(), &signature(), file, &tree, &parents).unwrap()
+}
+
+/// Stored repo on `main` with `tags` and nothing else, set other fields with
+/// `Repo { .., ..repo(..) }`
+pub fn repo<P: AsRef<Path>>(name: &str, path: P, tags: &[&str]) -> Repo {
+    Repo {
+        path: path.as_ref().to_path_buf(),
+        name: String::from(name),
+        branch: String::from("main"),
+        tags: tags.iter().map(|t| String::from(*t)).collect(),
+        ..Repo::default()
+    }
+}
@@ -1,11 +1,17 @@
-use crate::repo::Repos;
+use crate::repo::{Repos, Status, UpdateOutcome, UpdateProgress};
+use crossterm::{
+    execute,
+    terminal::{Clear, ClearType},
+    tty::IsTty,
+};
 use std::cmp::Eq;
+use std::io::{self, Write};
 use std::marker::Copy;
 use std::process;
 use std::path::PathBuf;
-use std::fs::{create_dir, write};
+use std::fs::create_dir;
+use std::time::{SystemTime, UNIX_EPOCH};
 use lazy_static::lazy_static;
-use log::info;
 
 pub fn toggle_item_in_vec<T: Eq + Copy>(list: &mut Vec<T>, item: T) {
     if list.contains(&item) {
@@ -23,14 +29,87 @@ pub fn remove_item_from_vec<T: Eq + Copy>(list: &mut Vec<T>, item: T) {
     list.retain(|&x| x != item);
 }
 
+/// Seconds since the epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Rough age of an epoch timestamp, e.g. `3 days ago`
+pub fn format_age(time: u64) -> String {
+    let secs = now().saturating_sub(time);
+    let (n, unit) = match secs {
+        0..=59 => return String::from("just now"),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        86400..=2591999 => (secs / 86400, "day"),
+        2592000..=31535999 => (secs / 2592000, "month"),
+        _ => (secs / 31536000, "year"),
+    };
+    format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" })
+}
+
 pub fn get_repos_or_exit() -> Repos {
     let repos = Repos::load();
     match repos {
         Ok(r) => r,
-        Err(_) => process::exit(1),
+        Err(e) => {
+            println!("Could not load repos: {}", e);
+            process::exit(1)
+        }
+    }
+}
+
+/// Like `get_repos_or_exit`, but through `Repos::read_only`
+pub fn read_repos_or_exit() -> Repos {
+    match Repos::read_only() {
+        Ok(r) => r,
+        Err(e) => {
+            println!("Could not load repos: {}", e);
+            process::exit(1)
+        }
+    }
+}
+
+fn status_display(status: &Option<Status>) -> String {
+    match status {
+        Some(status) => status.to_string(),
+        None => String::from("Unknown"),
     }
 }
 
+/// Print progress of `Repos::update`, changes and failures are kept on screen while the
+/// rest only update a single live line (when attached to a terminal).
+pub fn print_update_progress(progress: UpdateProgress) {
+    let mut stdout = io::stdout();
+    let live = stdout.is_tty();
+    if live {
+        let _ = execute!(stdout, Clear(ClearType::CurrentLine));
+        print!("\r");
+    }
+    let counter = format!("[{:>width$}/{}]", progress.done, progress.total, width = progress.total.to_string().len());
+    match progress.outcome {
+        UpdateOutcome::Changed(before, after) => println!(
+            "{} {}: {} -> {}",
+            counter,
+            progress.name,
+            status_display(before),
+            status_display(after)
+        ),
+        UpdateOutcome::Failed(e) => println!("{} {}: failed to open: {}", counter, progress.name, e),
+        UpdateOutcome::Unchanged => {}
+    }
+    if let Some(e) = progress.fetch_error {
+        println!("{} {}: failed to fetch: {}", counter, progress.name, e);
+    }
+    if live && progress.done < progress.total {
+        print!("{} {}", counter, progress.name);
+    }
+    let _ = stdout.flush();
+}
+
 lazy_static! {
     static ref RGM_DIR: PathBuf = {
         let mut home = dirs::home_dir().unwrap();
@@ -39,8 +118,8 @@ lazy_static! {
             if ! home.is_dir(){
                 panic!("$HOME/.rgm exists but is not a directory.")
             }
-        } else {
-            create_dir(&home);
+        } else if let Err(e) = create_dir(&home) {
+            panic!("Could not create $HOME/.rgm: {}", e);
         }
         home
     };
@@ -58,45 +137,3 @@ pub fn config_file() -> PathBuf {
     home
 }
 
-pub fn shell_file() -> PathBuf {
-    let mut home = RGM_DIR.clone();
-    home.push("rgm.sh");
-    home
-}
-
-pub fn clear_shell_file() {
-    let shell = shell_file();
-    write(&shell, "");
-}
-
-pub fn zsh_init() {
-    let zsh_function = r#"
-# Welcome to RGM!
-
-function rgm(){
-    rgm-bin "$@"
-    source $HOME/.rgm/rgm.sh
-}
-
-# To init rgm, add the following line to your $HOME/.zshrc:
-#
-# eval "$(rgm-bin init zsh)"
-    "#;
-    println!("{}", zsh_function);
-}
-
-pub fn bash_init() {
-    let zsh_function = r#"
-# Welcome to RGM!
-
-function rgm(){
-    rgm-bin "$@"
-    source $HOME/.rgm/rgm.sh
-}
-
-# To init rgm, add the following line to your $HOME/.bashrc:
-3
-# eval "$(rgm-bin init bash)"
-    "#;
-    println!("{}", zsh_function);
-}